@@ -0,0 +1,286 @@
+use spacetimedb::{
+    reducer, table, Table, SpacetimeType,
+    Identity, ReducerContext,
+};
+
+use super::{chunks::{self, schematic}, mesher, player, math::*, ticks};
+
+#[derive(SpacetimeType, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+// Privilege levels, ordered from lowest
+pub enum Role {
+    Moderator,
+    Admin,
+    Owner,
+}
+
+// Privileged identities
+#[table(name = admin, public)]
+pub struct Admin {
+    #[primary_key]
+    identity: Identity,
+    role: Role,
+}
+
+// Seed admins with the module owner
+pub fn init(ctx: &ReducerContext) {
+    if ctx.db.admin().identity().find(ctx.sender).is_none() {
+        ctx.db.admin().insert(Admin { identity: ctx.sender, role: Role::Owner });
+    }
+}
+
+/// Guard for privileged reducers, passes if sender has at least `role`
+pub fn require_role(ctx: &ReducerContext, role: Role) -> Result<Role, String> {
+    match ctx.db.admin().identity().find(ctx.sender) {
+        Some(admin) if admin.role >= role => Ok(admin.role),
+        _ => Err(format!("Only {:?} or higher may invoke this reducer.", role))
+    }
+}
+
+pub fn require_admin(ctx: &ReducerContext) -> Result<Role, String> {
+    require_role(ctx, Role::Admin)
+}
+
+// Rules for changing role of an existing admin, `None` revokes it
+fn check_change(ctx: &ReducerContext, sender: Role, admin: &Admin, role: Option<Role>) -> Result<(), String> {
+    if admin.role > sender {
+        return Err("Cannot change role of a higher admin.".into());
+    }
+
+    if admin.role != Role::Owner || role == Some(Role::Owner) {
+        return Ok(());
+    }
+
+    // Owners are changed only by another owner, and one always stays
+    if admin.identity == ctx.sender {
+        return Err("Owner role may be changed only by another owner.".into());
+    }
+
+    let owners = ctx.db.admin().iter().filter(|a| a.role == Role::Owner).count();
+    if owners <= 1 {
+        return Err("Cannot remove the last owner.".into());
+    }
+
+    Ok(())
+}
+
+#[reducer]
+pub fn grant_role(ctx: &ReducerContext, identity: Identity, role: Role) -> Result<(), String> {
+    let sender = require_admin(ctx)?;
+
+    // Nobody may grant a role above their own
+    if role > sender {
+        return Err("Cannot grant a role above your own.".into());
+    }
+
+    if let Some(admin) = ctx.db.admin().identity().find(identity) {
+        check_change(ctx, sender, &admin, Some(role))?;
+        ctx.db.admin().identity().update(Admin { identity, role });
+    } else {
+        ctx.db.admin().insert(Admin { identity, role });
+    }
+
+    log::info!("{} granted {:?} to {}", ctx.sender, role, identity);
+    Ok(())
+}
+
+#[reducer]
+pub fn revoke_role(ctx: &ReducerContext, identity: Identity) -> Result<(), String> {
+    let sender = require_admin(ctx)?;
+
+    let Some(admin) = ctx.db.admin().identity().find(identity) else {
+        return Err("Identity has no role.".into());
+    };
+
+    check_change(ctx, sender, &admin, None)?;
+    ctx.db.admin().identity().delete(identity);
+    log::info!("{} revoked {:?} from {}", ctx.sender, admin.role, identity);
+    Ok(())
+}
+
+#[reducer]
+/// Change world seed, applies to chunks generated from now on
+pub fn set_seed(ctx: &ReducerContext, seed: i32) -> Result<(), String> {
+    require_admin(ctx)?;
+
+    chunks::Context::set_seed(seed);
+    log::info!("World seed set to {} by {}", seed, ctx.sender);
+    Ok(())
+}
+
+#[reducer]
+pub fn kick_player(ctx: &ReducerContext, identity: Identity) -> Result<(), String> {
+    require_role(ctx, Role::Moderator)?;
+
+    player::kick(ctx, identity)?;
+    log::info!("{} kicked by {}", identity, ctx.sender);
+    Ok(())
+}
+
+#[reducer]
+/// Replace blocks schema with `blocks` json (`blocks.json` format),
+/// or the bundled one if none, and remesh every chunk
+pub fn reload_blocks(ctx: &ReducerContext, blocks: Option<String>) -> Result<(), String> {
+    require_admin(ctx)?;
+
+    chunks::reload_blocks(ctx, blocks.as_deref()).map_err(chunks::schema_errors)?;
+
+    // Unloaded chunks are remeshed when loaded again
+    mesher::Mesh::remove_unloaded(ctx);
+    let mut mesher = mesher::Mesher::get().write().unwrap();
+    for pos in chunks::LoadArea::positions() {
+        mesher.jobs.queue.push(pos);
+    }
+
+    log::info!("Blocks reloaded by {}", ctx.sender);
+    Ok(())
+}
+
+#[reducer]
+/// Wipe chunks in a box (chunk coords) and generate them again
+pub fn regenerate_area(ctx: &ReducerContext, min: StIVec3, max: StIVec3) -> Result<(), String> {
+    require_admin(ctx)?;
+
+    let count = chunks::regenerate(ctx, min.into(), max.into())?;
+    log::info!("{} chunks queued for regeneration by {}", count, ctx.sender);
+    Ok(())
+}
+
+// Resolve block name for edit reducers
+fn block_id(name: &str) -> Result<u16, String> {
+    let handler = chunks::BlocksHandler::get().read().unwrap();
+    handler.find_block(name).map_err(|e| e.to_string())
+}
+
+#[reducer]
+/// Set every block in a box (world coords)
+pub fn fill(ctx: &ReducerContext, min: StIVec3, max: StIVec3, block: String) -> Result<(), String> {
+    require_admin(ctx)?;
+    let id = block_id(&block)?;
+
+    let mut edit = chunks::WorldEdit::new(ctx);
+    chunks::for_each_block(min.into(), max.into(), |pos| edit.set_block(pos, id))?;
+
+    let count = edit.commit();
+    log::info!("Fill with {} changed {} chunks", block, count);
+    Ok(())
+}
+
+#[reducer]
+/// Replace one block type with another in a box (world coords)
+pub fn replace(
+    ctx: &ReducerContext,
+    min: StIVec3,
+    max: StIVec3,
+    from: String,
+    to: String
+) -> Result<(), String> {
+    require_admin(ctx)?;
+    let (from_id, to_id) = (block_id(&from)?, block_id(&to)?);
+
+    let mut edit = chunks::WorldEdit::new(ctx);
+    chunks::for_each_block(min.into(), max.into(), |pos| {
+        match edit.get_block(pos)? == from_id {
+            true => edit.set_block(pos, to_id),
+            false => Ok(())
+        }
+    })?;
+
+    let count = edit.commit();
+    log::info!("Replace {} with {} changed {} chunks", from, to, count);
+    Ok(())
+}
+
+#[reducer]
+/// Copy blocks from a box to `dst` (lowest corner), overlapping is allowed
+pub fn clone(
+    ctx: &ReducerContext,
+    src_min: StIVec3,
+    src_max: StIVec3,
+    dst: StIVec3
+) -> Result<(), String> {
+    require_admin(ctx)?;
+    let (src_min, src_max): (IVec3, IVec3) = (src_min.into(), src_max.into());
+    let (min, max) = (src_min.min(src_max), src_min.max(src_max));
+    let offset = IVec3::from(dst) - min;
+
+    // Read whole source first, so overlapping areas are copied intact
+    let mut edit = chunks::WorldEdit::new(ctx);
+    let mut blocks = Vec::new();
+    chunks::for_each_block(min, max, |pos| {
+        blocks.push((pos + offset, edit.get_block(pos)?));
+        Ok(())
+    })?;
+
+    for (pos, id) in blocks {
+        edit.set_block(pos, id)?;
+    }
+
+    let count = edit.commit();
+    log::info!("Clone changed {} chunks", count);
+    Ok(())
+}
+
+#[reducer]
+/// Save blocks in a box (world coords) as a named schematic
+pub fn save_schematic(
+    ctx: &ReducerContext,
+    name: String,
+    min: StIVec3,
+    max: StIVec3
+) -> Result<(), String> {
+    require_admin(ctx)?;
+
+    if ctx.db.schematic().name().find(&name).is_some() {
+        return Err(format!("Schematic \"{}\" already exists", name));
+    }
+
+    let schematic = chunks::Schematic::save(ctx, name, min.into(), max.into())?;
+    log::info!("Schematic {} saved by {}", schematic.name, ctx.sender);
+    ctx.db.schematic().insert(schematic);
+    Ok(())
+}
+
+#[reducer]
+/// Paste schematic with its lowest corner at `pos`, rotated by quarter turns
+pub fn paste_schematic(
+    ctx: &ReducerContext,
+    name: String,
+    pos: StIVec3,
+    rotation: u8,
+    include_air: bool
+) -> Result<(), String> {
+    require_admin(ctx)?;
+
+    let Some(schematic) = ctx.db.schematic().name().find(&name) else {
+        return Err(format!("Schematic \"{}\" is not found", name));
+    };
+
+    let count = schematic.paste(ctx, pos.into(), rotation, include_air)?;
+    log::info!("Schematic {} pasted, {} chunks changed", name, count);
+    Ok(())
+}
+
+#[reducer]
+pub fn delete_schematic(ctx: &ReducerContext, name: String) -> Result<(), String> {
+    require_admin(ctx)?;
+
+    match ctx.db.schematic().name().delete(&name) {
+        true => Ok(()),
+        false => Err(format!("Schematic \"{}\" is not found", name))
+    }
+}
+
+#[reducer]
+/// Change tick work budget, microseconds
+pub fn set_tick_budget(ctx: &ReducerContext, budget: u64) -> Result<(), String> {
+    require_admin(ctx)?;
+
+    let Some(mut ticks) = ctx.db.ticks().id().find(0) else {
+        return Err("Ticks loop is not running".into());
+    };
+
+    ticks.budget = budget;
+    ctx.db.ticks().id().update(ticks);
+    log::info!("Tick budget set to {}us by {}", budget, ctx.sender);
+    Ok(())
+}
@@ -1,9 +1,53 @@
 use log::debug;
-use spacetimedb::{table, ReducerContext, Table};
+use spacetimedb::{table, ReducerContext, Table, SpacetimeType};
 use include_directory::{include_directory, Dir};
 
 static ASSETS_DIR: Dir<'_> = include_directory!("./assets");
 
+#[derive(SpacetimeType, Clone, Copy, Debug, PartialEq, Eq)]
+// Asset kind from file extension
+pub enum AssetKind {
+    Texture,
+    Shader,
+    Json,
+    Binary,
+}
+
+impl AssetKind {
+    pub fn from_name(name: &str) -> Self {
+        let extension = name.rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "png" => Self::Texture,
+            "wgsl" => Self::Shader,
+            "json" => Self::Json,
+            _ => Self::Binary
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Texture => "image/png",
+            Self::Shader => "text/wgsl",
+            Self::Json => "application/json",
+            Self::Binary => "application/octet-stream",
+        }
+    }
+}
+
+/// FNV-1a content hash.
+/// Stable across builds, so clients can cache assets by it
+pub fn content_hash(value: &[u8]) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    value.iter().fold(OFFSET, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(PRIME)
+    })
+}
+
 // Assets data
 #[table(name = asset, public)]
 pub struct StAsset {
@@ -12,34 +56,120 @@ pub struct StAsset {
     id: u64,
     #[unique]
     name: String,
+    // Content hash, changes only with the file contents
+    hash: u64,
+    size: u64,
+    kind: AssetKind,
+    mime: String,
+    // Parts count in `asset_part`; zero if value is stored inline.
+    // Clients join parts ordered by index and check the hash
+    parts: u32,
+    part_size: u32,
+    value: Vec<u8>
+}
+
+// Part of a large asset, ordered by index
+#[table(name = asset_part, public)]
+pub struct StAssetPart {
+    #[auto_inc]
+    #[primary_key]
+    id: u64,
+    #[index(btree)]
+    asset: u64,
+    index: u32,
+    hash: u64,
     value: Vec<u8>
 }
 
+// Assets larger than one part are stored chunked
+pub const PART_SIZE: usize = 64 * 1024;
+
+impl StAsset {
+    /// Set asset value, splitting it into parts if needed
+    fn store(mut self, ctx: &ReducerContext, value: Vec<u8>) {
+        ctx.db.asset_part().asset().delete(self.id);
+
+        self.hash = content_hash(&value);
+        self.size = value.len() as u64;
+        self.part_size = PART_SIZE as u32;
+
+        if value.len() <= PART_SIZE {
+            self.parts = 0;
+            self.value = value;
+            ctx.db.asset().id().update(self);
+            return;
+        }
+
+        let parts = value.chunks(PART_SIZE).collect::<Vec<_>>();
+        self.parts = parts.len() as u32;
+        self.value = Vec::new();
+        let asset = ctx.db.asset().id().update(self);
+
+        for (index, part) in parts.into_iter().enumerate() {
+            ctx.db.asset_part().insert(StAssetPart {
+                id: 0,
+                asset: asset.id,
+                index: index as u32,
+                hash: content_hash(part),
+                value: part.to_vec()
+            });
+        }
+    }
+}
+
+/// Check that asset file is bundled
+pub fn exists(name: &str) -> bool {
+    ASSETS_DIR.get_file(name).is_some()
+}
+
 // todo: create assets trees
 // todo: create recursive read (load) assets function
 
 pub fn load(ctx: &ReducerContext) {
     debug!("Loading assets...");
 
+    let mut names = Vec::new();
     for file in ASSETS_DIR.files() {
         let path = file.path();
         let name = String::from(
             path.file_name().unwrap().to_str().unwrap()
         );
         let value = file.contents().to_vec();
+        names.push(name.clone());
+
+        if let Some(dbfile) = ctx.db.asset().name().find(&name) {
+            // Keep row untouched, so clients are not notified
+            if dbfile.hash == content_hash(&value) {
+                continue;
+            }
 
-        if let Some(mut dbfile) = ctx.db.asset().name().find(&name) {
-            dbfile.value = value;
-            
-            ctx.db.asset().id().update(dbfile);
+            debug!("Asset changed: {}", name);
+            dbfile.store(ctx, value);
             continue;
         }
 
-        ctx.db.asset().insert(StAsset {
+        let kind = AssetKind::from_name(&name);
+        let dbfile = ctx.db.asset().insert(StAsset {
             id: 0,
             name,
-            value
+            hash: 0,
+            size: 0,
+            kind,
+            mime: kind.mime().to_string(),
+            parts: 0,
+            part_size: 0,
+            value: Vec::new()
         });
+
+        dbfile.store(ctx, value);
     }
 
-}
\ No newline at end of file
+    // Remove assets that no longer exist
+    for asset in ctx.db.asset().iter() {
+        if !names.contains(&asset.name) {
+            debug!("Asset removed: {}", asset.name);
+            ctx.db.asset_part().asset().delete(asset.id);
+            ctx.db.asset().id().delete(asset.id);
+        }
+    }
+}
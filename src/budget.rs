@@ -0,0 +1,100 @@
+/// Per-tick work budget in microseconds.
+///
+/// Reducers have no clock, so work is charged by estimated cost.
+/// `scale` corrects estimates by how late ticks actually run
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    limit: u64,
+    spent: u64,
+    scale: f64,
+}
+
+impl Budget {
+    // Bounds of cost estimates correction
+    pub const MIN_SCALE: f64 = 0.25;
+    pub const MAX_SCALE: f64 = 16.0;
+
+    pub fn new(limit: u64, scale: f64) -> Self {
+        Self { limit, spent: 0, scale }
+    }
+
+    pub fn charge(&mut self, cost: u64) {
+        self.spent += (cost as f64 * self.scale) as u64;
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.spent >= self.limit
+    }
+
+    pub fn spent(&self) -> u64 {
+        self.spent
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.spent)
+    }
+
+    /// Phase budget of `share` of what is left, give it back with `merge`
+    pub fn share(&self, share: f64) -> Self {
+        Self::new((self.remaining() as f64 * share) as u64, self.scale)
+    }
+
+    pub fn merge(&mut self, phase: Budget) {
+        self.spent += phase.spent;
+    }
+
+    /// New estimates correction from tick interval:
+    /// grow when ticks run late, shrink back when on time
+    pub fn adapt(scale: f64, interval: i64, target: i64) -> f64 {
+        let scale = match interval {
+            i if i * 4 > target * 5 => scale * 1.1,
+            i if i * 20 < target * 21 => scale * 0.95,
+            _ => scale
+        };
+
+        scale.clamp(Self::MIN_SCALE, Self::MAX_SCALE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges_are_scaled() {
+        let mut budget = Budget::new(1_000, 2.0);
+        budget.charge(300);
+        assert_eq!(budget.spent(), 600);
+        assert!(!budget.exhausted());
+
+        budget.charge(200);
+        assert!(budget.exhausted());
+        assert_eq!(budget.remaining(), 0);
+    }
+
+    #[test]
+    fn shares_leave_budget_for_later_phases() {
+        let mut budget = Budget::new(1_000, 1.0);
+
+        let mut first = budget.share(0.5);
+        while !first.exhausted() {
+            first.charge(200);
+        }
+        budget.merge(first);
+        assert_eq!(budget.spent(), 600);
+
+        let second = budget.share(1.0);
+        assert_eq!(second.remaining(), 400);
+        assert!(!second.exhausted());
+    }
+
+    #[test]
+    fn adapt_follows_tick_interval() {
+        assert!(Budget::adapt(1.0, 100, 50) > 1.0);
+        assert!(Budget::adapt(1.0, 50, 50) < 1.0);
+        assert_eq!(Budget::adapt(1.0, 55, 50), 1.0);
+
+        assert_eq!(Budget::adapt(Budget::MAX_SCALE, 1_000, 50), Budget::MAX_SCALE);
+        assert_eq!(Budget::adapt(Budget::MIN_SCALE, 10, 50), Budget::MIN_SCALE);
+    }
+}
@@ -0,0 +1,89 @@
+use super::*;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+/// Biome declared in generator config
+pub struct Biome {
+    pub name: String,
+    // Climate ranges, both from 0 to 1
+    pub temperature: (f32, f32),
+    pub humidity: (f32, f32),
+    // Top block, blocks under it and the rest
+    pub surface: String,
+    pub filler: String,
+    #[serde(default = "Biome::default_filler_depth")]
+    pub filler_depth: i32,
+    pub base: String,
+    // Terrain height in blocks: height +- amplitude
+    pub height: f32,
+    pub amplitude: f32,
+    // Feature names allowed in this biome
+    #[serde(default)]
+    pub features: Vec<String>,
+    // Grass tint for clients, RGB
+    #[serde(default = "Biome::default_tint")]
+    pub tint: [u8; 3],
+}
+
+impl Biome {
+    fn default_filler_depth() -> i32 { 3 }
+    fn default_tint() -> [u8; 3] { [255, 255, 255] }
+
+    // Distance from climate point to biome ranges, zero inside
+    pub fn climate_distance(&self, climate: Vec2) -> f32 {
+        let axis = |v: f32, (min, max): (f32, f32)| (min - v).max(v - max).max(0.0);
+        vec2(axis(climate.x, self.temperature), axis(climate.y, self.humidity)).length()
+    }
+}
+
+// Biome types for clients
+#[table(name = biome, public)]
+pub struct StBiome {
+    #[primary_key]
+    id: u8,
+    #[unique]
+    name: String,
+    tint: u32,
+}
+
+/// Biome ids of a chunk column, `x + z * SIZE` order.
+/// 
+/// Clients look up `biome_at(x, z)` by `StIVec3::key` of chunk (x, 0, z)
+/// and the local column index, then `biome` for name and tint
+#[table(name = biome_column, public)]
+pub struct BiomeColumn {
+    #[primary_key]
+    key: u64,
+    position: StIVec3,
+    biomes: Vec<u8>,
+}
+
+impl BiomeColumn {
+    // Store biomes of chunk column, replacing ones of an older seed
+    pub fn store(ctx: &ReducerContext, chunk: IVec3, biomes: Vec<u8>) {
+        let position = StIVec3::from(ivec3(chunk.x, 0, chunk.z));
+        let key = position.key();
+
+        match ctx.db.biome_column().key().find(key) {
+            Some(column) if column.biomes == biomes => {},
+            Some(_) => { ctx.db.biome_column().key().update(BiomeColumn { key, position, biomes }); },
+            None => { ctx.db.biome_column().insert(BiomeColumn { key, position, biomes }); }
+        }
+    }
+}
+
+// Write biome types from config
+pub fn init_biomes(ctx: &ReducerContext) {
+    for biome in ctx.db.biome().iter() {
+        ctx.db.biome().id().delete(biome.id);
+    }
+
+    let context = Context::get().read().unwrap();
+    for (id, biome) in context.config.biomes.iter().enumerate() {
+        let [r, g, b] = biome.tint;
+        ctx.db.biome().insert(StBiome {
+            id: id as u8,
+            name: biome.name.clone(),
+            tint: (r as u32) << 16 | (g as u32) << 8 | b as u32,
+        });
+    }
+}
@@ -3,7 +3,7 @@ use spacetimedb::{table, ReducerContext, Table, SpacetimeType};
 
 #[derive(Debug)]
 pub struct BlocksHandler {
-    values: Vec<Arc<Block>>,
+    values: HashMap<u16, Arc<Block>>,
     names: HashMap<String, Arc<Block>>
 }
 
@@ -11,8 +11,14 @@ static VALUE: OnceLock<RwLock<BlocksHandler>> = OnceLock::new();
 
 impl BlocksHandler {
     pub fn new(ctx: &ReducerContext) -> Self {
-        let values: Vec<Arc<Block>> = ctx.db.block().iter().map(|b| Arc::new(b)).collect();
-        let names = HashMap::from_iter(values.iter().map(|v| (v.name.clone(), v.clone())));
+        Self::from_blocks(ctx.db.block().iter())
+    }
+
+    pub fn from_blocks(blocks: impl IntoIterator<Item = Block>) -> Self {
+        let values: HashMap<u16, Arc<Block>> = blocks.into_iter()
+            .map(|b| (b.id, Arc::new(b)))
+            .collect();
+        let names = HashMap::from_iter(values.values().map(|v| (v.name.clone(), v.clone())));
 
         Self { values, names }
     }
@@ -21,17 +27,49 @@ impl BlocksHandler {
         VALUE.set(RwLock::new(Self::new(ctx))).unwrap();
     }
 
+    // Swap handler contents with current `block` table
+    pub fn reload(ctx: &ReducerContext) {
+        *Self::get().write().unwrap() = Self::new(ctx);
+    }
+
     pub fn get() -> &'static RwLock<Self> {
         VALUE.get().unwrap()
     }
 
-    pub fn find_block(&self, name: &str) -> u16 {
-        self.names.get(name).cloned()
-            .and_then(|b| Some(b.id)).unwrap_or(0)
+    pub fn find_block(&self, name: &str) -> Result<u16, BlockError> {
+        self.names.get(name)
+            .map(|b| b.id)
+            .ok_or_else(|| BlockError::UnknownName(name.to_string()))
+    }
+
+    pub fn block(&self, id: u16) -> Result<Arc<Block>, BlockError> {
+        self.values.get(&id).cloned()
+            .ok_or(BlockError::UnknownId(id))
     }
 
-    pub fn block(&self, id: u16) -> Option<Arc<Block>> {
-        self.values.get(id as usize).cloned()
+    /// Placeholder for ids missing from the registry.
+    /// Always present, checked by schema validation
+    pub fn unknown(&self) -> Arc<Block> {
+        self.names[UNKNOWN].clone()
+    }
+}
+
+// Placeholder block name
+pub const UNKNOWN: &str = "unknown";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Blocks registry lookup errors
+pub enum BlockError {
+    UnknownName(String),
+    UnknownId(u16),
+}
+
+impl std::fmt::Display for BlockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownName(name) => write!(f, "Unknown block name \"{name}\""),
+            Self::UnknownId(id) => write!(f, "Unknown block id {id}"),
+        }
     }
 }
 
@@ -46,6 +84,14 @@ pub enum ModelType {
 }
 
 impl ModelType {
+    // Texture file name, if model has one
+    pub fn texture(&self) -> Option<&str> {
+        match self {
+            Self::Empty => None,
+            Self::Cube(t) | Self::Stair(t) | Self::Slab(t) => Some(t)
+        }
+    }
+
     pub fn is_meshable(&self) -> bool {
         match self {
             Self::Cube(_) => true,
@@ -74,4 +120,86 @@ impl Block {
     pub fn is_meshable(&self) -> bool {
         self.model.is_meshable()
     }
-}
\ No newline at end of file
+}
+
+/// Every block name ever declared and its id.
+///
+/// Only appended, so ids of removed blocks stay reserved
+/// and their blocks come back if the name is declared again
+#[table(name = block_id)]
+pub struct BlockId {
+    #[primary_key]
+    pub name: String,
+    #[unique]
+    pub id: u16,
+}
+
+// Block ids are packed in 12 bits
+pub const MAX_BLOCKS: usize = 1 << 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Blocks schema errors
+pub enum SchemaError {
+    NotFound,
+    Parse(String),
+    MissingTexture { block: String, texture: String },
+    DuplicateName(String),
+    TooManyBlocks(usize),
+    IdsExhausted,
+    AirNotFirst,
+    MissingPlaceholder,
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Blocks data file is not found"),
+            Self::Parse(e) => write!(f, "Blocks data file parse error: {e}"),
+            Self::MissingTexture { block, texture } => {
+                write!(f, "Block \"{block}\" uses missing texture \"{texture}\"")
+            },
+            Self::DuplicateName(name) => write!(f, "Block \"{name}\" is declared twice"),
+            Self::TooManyBlocks(n) => write!(f, "{n} blocks declared, limit is {MAX_BLOCKS}"),
+            Self::IdsExhausted => write!(f, "All {MAX_BLOCKS} block ids were used by declared or removed blocks"),
+            Self::AirNotFirst => write!(f, "Block \"air\" must have id 0"),
+            Self::MissingPlaceholder => write!(f, "Block \"{UNKNOWN}\" is not declared"),
+        }
+    }
+}
+
+/// Validate parsed blocks, collecting every error
+pub fn validate_blocks(blocks: &[(String, ModelType)]) -> Result<(), Vec<SchemaError>> {
+    let mut errors = Vec::new();
+
+    if blocks.len() > MAX_BLOCKS {
+        errors.push(SchemaError::TooManyBlocks(blocks.len()));
+    }
+
+    if blocks.first().is_none_or(|(name, _)| name != "air") {
+        errors.push(SchemaError::AirNotFirst);
+    }
+
+    if !blocks.iter().any(|(name, _)| name == UNKNOWN) {
+        errors.push(SchemaError::MissingPlaceholder);
+    }
+
+    let mut names = std::collections::HashSet::new();
+    for (name, model) in blocks {
+        if !names.insert(name.as_str()) {
+            errors.push(SchemaError::DuplicateName(name.clone()));
+        }
+
+        if let Some(texture) = model.texture()
+            && !crate::assets::exists(texture) {
+            errors.push(SchemaError::MissingTexture {
+                block: name.clone(),
+                texture: texture.to_string()
+            });
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors)
+    }
+}
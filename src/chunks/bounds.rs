@@ -0,0 +1,235 @@
+use super::*;
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+/// World bounds in blocks, inclusive.
+///
+/// Missing axis range means the world is infinite on it
+pub struct Bounds {
+    #[serde(default)]
+    pub x: Option<(i32, i32)>,
+    #[serde(default)]
+    pub z: Option<(i32, i32)>,
+    // Build height
+    #[serde(default)]
+    pub min_height: Option<i32>,
+    #[serde(default)]
+    pub max_height: Option<i32>,
+}
+
+impl Bounds {
+    // Blocks of chunks with unique `StIVec3::key`, infinite axes end here
+    pub const MIN_BLOCK: i32 = StIVec3::KEY_MIN * SIZE_I32;
+    pub const MAX_BLOCK: i32 = (StIVec3::KEY_MAX + 1) * SIZE_I32 - 1;
+
+    fn axes(&self) -> [(i32, i32); 3] {
+        let axis = |r: Option<(i32, i32)>| r.unwrap_or((Self::MIN_BLOCK, Self::MAX_BLOCK));
+        [
+            axis(self.x),
+            (self.min_height.unwrap_or(Self::MIN_BLOCK), self.max_height.unwrap_or(Self::MAX_BLOCK)),
+            axis(self.z),
+        ]
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let axes = self.axes();
+        if axes.iter().any(|(min, max)| min > max) {
+            return Err("World bounds min must not exceed max".into());
+        }
+
+        match axes.iter().all(|(min, max)| *min >= Self::MIN_BLOCK && *max <= Self::MAX_BLOCK) {
+            true => Ok(()),
+            false => Err(format!("World bounds must be within {}..{}", Self::MIN_BLOCK, Self::MAX_BLOCK))
+        }
+    }
+
+    pub fn contains_block(&self, pos: IVec3) -> bool {
+        self.axes().iter().enumerate()
+            .all(|(axis, (min, max))| (*min..=*max).contains(&pos[axis]))
+    }
+
+    /// Chunk has at least one block inside bounds
+    pub fn contains_chunk(&self, chunk: IVec3) -> bool {
+        let (min, max) = (chunk * SIZE_I32, chunk * SIZE_I32 + IVec3::splat(SIZE_I32 - 1));
+        self.axes().iter().enumerate()
+            .all(|(axis, (lo, hi))| min[axis] <= *hi && max[axis] >= *lo)
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+// Chunk loading radii
+pub struct Loading {
+    // Chunks queued around spawn at startup
+    pub spawn_radius: i32,
+    // Chunks queued around each scanner
+    pub view_radius: i32,
+}
+
+impl Default for Loading {
+    fn default() -> Self {
+        Self { spawn_radius: 10, view_radius: 8 }
+    }
+}
+
+/// Queue generation and meshing of chunks around `center`,
+/// skipping ones outside world bounds or already loaded or queued.
+/// Evicted chunks are loaded back from database
+pub fn load_around(ctx: &ReducerContext, center: IVec3, radius: i32) -> usize {
+    let context = Context::get().read().unwrap();
+    let bounds = &context.config.bounds;
+
+    let mut generator = Generator::get().write().unwrap();
+    let mut mesher = Mesher::get().write().unwrap();
+
+    let mut count = 0;
+    for x in -radius..=radius {
+        for y in -radius..=radius {
+            for z in -radius..=radius {
+                let pos = center + ivec3(x, y, z);
+                if !bounds.contains_chunk(pos)
+                    || LoadArea::get(&pos).is_some()
+                    || generator.jobs.is_pending(&pos) {
+                    continue;
+                }
+
+                if let Some(chunk) = ctx.db.chunk().key().find(StIVec3::from(pos).key()) {
+                    LoadArea::insert(pos, Arc::new(chunk));
+                    mesher.notify(pos);
+
+                    // Mesh may be cancelled before it was built
+                    match Mesh::exists(ctx, pos) {
+                        true => ChunkStatus::set(ctx, pos, ChunkState::Meshed),
+                        false => {
+                            mesher.jobs.queue.push(pos);
+                            ChunkStatus::set(ctx, pos, ChunkState::Generated);
+                        }
+                    }
+                    continue;
+                }
+
+                generator.jobs.queue.push(pos);
+                mesher.jobs.queue.push(pos);
+                Latency::enqueued(pos, ctx.timestamp);
+                ChunkStatus::set(ctx, pos, ChunkState::Queued);
+                count += 1;
+            }
+        }
+    }
+
+    count
+}
+
+// Extra chunks kept loaded and queued beyond scanner view radius
+pub const EVICT_MARGIN: i32 = 2;
+// Estimated cost of evicting a chunk or cancelling its jobs, microseconds
+pub const EVICT_COST: u64 = 20;
+
+// Chunk is near spawn or any scanner
+fn in_view(pos: IVec3, centers: &[IVec3], loading: &Loading) -> bool {
+    pos.abs().max_element() <= loading.spawn_radius
+        || centers.iter().any(|c| (pos - *c).abs().max_element() <= loading.view_radius + EVICT_MARGIN)
+}
+
+/// Cancel generation and meshing of chunks out of view,
+/// `load_around` queues them again
+pub fn proceed_cancellation(ctx: &ReducerContext, centers: &[IVec3], budget: &mut Budget) -> u32 {
+    let context = Context::get().read().unwrap();
+    let loading = &context.config.loading;
+
+    let mut generator = Generator::get().write().unwrap();
+    let mut mesher = Mesher::get().write().unwrap();
+    let mut count = 0;
+
+    for pos in generator.jobs.pending() {
+        if !in_view(pos, centers, loading) && generator.jobs.cancel(&pos) {
+            mesher.abandon(pos);
+            Latency::cancel(pos);
+            ChunkStatus::set(ctx, pos, ChunkState::Unloaded);
+            budget.charge(EVICT_COST);
+            count += 1;
+        }
+    }
+
+    for pos in mesher.pending() {
+        if !in_view(pos, centers, loading) && mesher.cancel(&pos) {
+            // Old mesh is stale, `load_around` remeshes chunks without one
+            Mesh::remove(ctx, pos);
+            Latency::cancel(pos);
+            ChunkStatus::set(ctx, pos, ChunkState::Unloaded);
+            budget.charge(EVICT_COST);
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// Unloads chunks far from spawn and every scanner,
+/// rows are kept and loaded back by `load_around`
+#[derive(Debug, Default)]
+pub struct Evictor {
+    pub jobs: Runner<()>,
+    centers: Vec<IVec3>,
+}
+
+static EVICTOR: OnceLock<RwLock<Evictor>> = OnceLock::new();
+
+impl Evictor {
+    pub fn init() {
+        EVICTOR.set(RwLock::new(Self::default())).unwrap();
+    }
+
+    pub fn get() -> &'static RwLock<Self> {
+        EVICTOR.get().unwrap()
+    }
+}
+
+impl Job for Evictor {
+    type Output = ();
+
+    const NAME: &'static str = "evictor";
+    const TASK_COST: u64 = EVICT_COST;
+    const STORE_COST: u64 = 0;
+    const INLINE: bool = true;
+    const RUNNING: ChunkState = ChunkState::Unloaded;
+    const DONE: ChunkState = ChunkState::Unloaded;
+
+    fn runner(&mut self) -> &mut Runner<()> {
+        &mut self.jobs
+    }
+
+    // Nothing to compute, chunk is dropped on completion
+    fn start(&mut self, _ctx: &ReducerContext, pos: IVec3) -> Option<impl FnOnce() -> Result<(), String> + Send + 'static> {
+        let context = Context::get().read().unwrap();
+        match in_view(pos, &self.centers, &context.config.loading) {
+            true => None,
+            false => Some(|| Ok(()))
+        }
+    }
+
+    fn complete(&mut self, ctx: &ReducerContext, pos: IVec3, _output: ()) {
+        LoadArea::remove(&pos);
+
+        // Dropped remesh leaves a stale mesh, rebuilt on next load
+        if Mesher::get().write().unwrap().cancel(&pos) {
+            Mesh::remove(ctx, pos);
+        }
+    }
+}
+
+/// Queue loaded chunks out of view for eviction and proceed it,
+/// returns evicted chunks count
+pub fn proceed_eviction(ctx: &ReducerContext, centers: &[IVec3], budget: &mut Budget) -> u32 {
+    let mut evictor = Evictor::get().write().unwrap();
+    evictor.centers = centers.to_vec();
+
+    {
+        let context = Context::get().read().unwrap();
+        for pos in LoadArea::positions() {
+            if !in_view(pos, centers, &context.config.loading) && !evictor.jobs.is_pending(&pos) {
+                evictor.jobs.queue.push(pos);
+            }
+        }
+    }
+
+    jobs::proceed(&mut *evictor, ctx, budget)
+}
@@ -0,0 +1,99 @@
+use fastnoise_lite::FastNoiseLite;
+use super::*;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+/// 3D density config.
+///
+/// Block is solid if `(height - y) * falloff + noise * amplitude > threshold`,
+/// cave noise above `cave_threshold` carves it out
+pub struct Density {
+    pub frequency: f32,
+    pub amplitude: f32,
+    pub falloff: f32,
+    pub threshold: f32,
+    pub cave_frequency: f32,
+    pub cave_threshold: f32,
+    // Coarse grid step in blocks, must divide chunk size
+    pub grid: i32,
+}
+
+impl Default for Density {
+    fn default() -> Self {
+        Self {
+            frequency: 0.02,
+            amplitude: 8.0,
+            falloff: 1.0,
+            threshold: 0.0,
+            cave_frequency: 0.03,
+            cave_threshold: 0.6,
+            grid: 4,
+        }
+    }
+}
+
+impl Density {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.grid > 0 && SIZE_I32 % self.grid == 0 {
+            true => Ok(()),
+            false => Err(format!("Density grid {} must divide chunk size {}", self.grid, SIZE))
+        }
+    }
+
+    pub fn is_solid(&self, height: i32, y: i32, sample: Vec2) -> bool {
+        let density = (height - y) as f32 * self.falloff + sample.x * self.amplitude;
+        density > self.threshold && sample.y <= self.cave_threshold
+    }
+}
+
+/// Density and cave noise sampled on a coarse grid
+/// over a chunk and `grid` blocks above it
+pub struct DensityGrid {
+    step: i32,
+    size: IVec3,
+    // (density, cave) noise pairs
+    values: Vec<Vec2>,
+}
+
+impl DensityGrid {
+    pub fn sample(density: &FastNoiseLite, caves: &FastNoiseLite, config: &Density, origin: IVec3) -> Self {
+        let step = config.grid;
+        let points = SIZE_I32 / step + 1;
+        let size = ivec3(points, points + 1, points);
+
+        let mut values = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let p = (origin + ivec3(x, y, z) * step).as_vec3();
+                    values.push(vec2(
+                        density.get_noise_3d(p.x, p.y, p.z),
+                        caves.get_noise_3d(p.x, p.y, p.z)
+                    ));
+                }
+            }
+        }
+
+        Self { step, size, values }
+    }
+
+    fn value(&self, x: i32, y: i32, z: i32) -> Vec2 {
+        self.values[(x + z * self.size.x + y * self.size.x * self.size.z) as usize]
+    }
+
+    /// Trilinear interpolated sample at local block position
+    pub fn get(&self, local: IVec3) -> Vec2 {
+        let cell = local / self.step;
+        let t = (local - cell * self.step).as_vec3() / self.step as f32;
+        let (x, y, z) = (cell.x, cell.y, cell.z);
+
+        let lerp_x = |y: i32, z: i32| self.value(x, y, z).lerp(self.value(x + 1, y, z), t.x);
+        let lerp_z = |y: i32| lerp_x(y, z).lerp(lerp_x(y, z + 1), t.z);
+
+        lerp_z(y).lerp(lerp_z(y + 1), t.y)
+    }
+
+    /// Highest local y the grid covers (exclusive)
+    pub fn height(&self) -> i32 {
+        (self.size.y - 1) * self.step
+    }
+}
@@ -0,0 +1,133 @@
+use super::*;
+
+// Max blocks touched by one edit
+pub const MAX_EDIT_VOLUME: usize = 64 * 64 * 64;
+
+/// Chunk position and local block position from world block position
+pub fn split_world(pos: IVec3) -> (IVec3, IVec3) {
+    (pos.div_euclid(IVec3::splat(SIZE_I32)), pos.rem_euclid(IVec3::splat(SIZE_I32)))
+}
+
+/// Block box size, checked against edit limit
+pub fn edit_size(min: IVec3, max: IVec3) -> Result<IVec3, String> {
+    let size = (max - min).abs() + IVec3::ONE;
+    let volume = size.x as usize * size.y as usize * size.z as usize;
+
+    if volume > MAX_EDIT_VOLUME {
+        return Err(format!("Edit of {volume} blocks exceeds limit of {MAX_EDIT_VOLUME}"));
+    }
+
+    Ok(size)
+}
+
+/// Batched block edits across chunk boundaries.
+///
+/// Chunks are loaded on first access and written back on `commit`
+pub struct WorldEdit<'a> {
+    ctx: &'a ReducerContext,
+    chunks: HashMap<IVec3, Chunk>,
+    changed: HashSet<IVec3>,
+    remesh: HashSet<IVec3>,
+    bounds: Option<Bounds>,
+}
+
+impl<'a> WorldEdit<'a> {
+    pub fn new(ctx: &'a ReducerContext) -> Self {
+        Self {
+            ctx,
+            chunks: HashMap::new(),
+            changed: HashSet::new(),
+            remesh: HashSet::new(),
+            bounds: Some(Context::get().read().unwrap().config.bounds.clone())
+        }
+    }
+
+    // Skip world bounds check, for writes already checked by generator
+    pub fn unbounded(mut self) -> Self {
+        self.bounds = None;
+        self
+    }
+
+    fn chunk(&mut self, pos: IVec3) -> Result<&mut Chunk, String> {
+        if !self.chunks.contains_key(&pos) {
+            let chunk = self.ctx.db.chunk().key().find(StIVec3::from(pos).key())
+                .ok_or_else(|| format!("Chunk {} is not generated", pos))?;
+
+            self.chunks.insert(pos, chunk);
+        }
+
+        Ok(self.chunks.get_mut(&pos).unwrap())
+    }
+
+    pub fn get_block(&mut self, pos: IVec3) -> Result<u16, String> {
+        let (chunk, local) = split_world(pos);
+        Ok(self.chunk(chunk)?.get_block(Chunk::block_index(local)))
+    }
+
+    pub fn set_block(&mut self, pos: IVec3, id: u16) -> Result<(), String> {
+        if self.bounds.as_ref().is_some_and(|b| !b.contains_block(pos)) {
+            return Err(format!("Block {} is outside of world bounds", pos));
+        }
+
+        let (chunk_pos, local) = split_world(pos);
+        let chunk = self.chunk(chunk_pos)?;
+        let index = Chunk::block_index(local);
+
+        if chunk.get_block(index) == id {
+            return Ok(());
+        }
+
+        chunk.set_block(index, id);
+        self.changed.insert(chunk_pos);
+        self.remesh.insert(chunk_pos);
+
+        // Border blocks are visible from the neighbour mesh
+        for axis in 0..3 {
+            let offset = IVec3::AXES[axis];
+            if local[axis] == 0 {
+                self.remesh.insert(chunk_pos - offset);
+            }
+            if local[axis] == SIZE_I32 - 1 {
+                self.remesh.insert(chunk_pos + offset);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write changed chunks and queue remeshing, returns changed chunks count
+    pub fn commit(self) -> usize {
+        let mut mesher = crate::mesher::Mesher::get().write().unwrap();
+
+        for (pos, chunk) in self.chunks {
+            if self.changed.contains(&pos) {
+                let chunk = self.ctx.db.chunk().id().update(chunk);
+                LoadArea::insert(pos, Arc::new(chunk));
+            }
+        }
+
+        mesher.jobs.queue.extend(self.remesh);
+
+        self.changed.len()
+    }
+}
+
+/// Iterate all block positions in a box
+pub fn for_each_block(
+    min: IVec3,
+    max: IVec3,
+    mut f: impl FnMut(IVec3) -> Result<(), String>
+) -> Result<(), String> {
+    let (min, max) = (min.min(max), min.max(max));
+    edit_size(min, max)?;
+
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                f(ivec3(x, y, z))?;
+            }
+        }
+    }
+
+    Ok(())
+}
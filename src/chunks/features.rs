@@ -0,0 +1,317 @@
+use spacetimedb::SpacetimeType;
+use super::*;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+// Feature shape and its blocks
+pub enum FeatureKind {
+    Tree { trunk: String, leaves: String, min_height: i32, max_height: i32, radius: i32 },
+    Vein { block: String, size: u32 },
+    Boulder { block: String, radius: i32 },
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+/// Decoration feature with spawn rules
+pub struct Feature {
+    pub name: String,
+    pub kind: FeatureKind,
+    // Average placement attempts per chunk
+    pub per_chunk: f32,
+    // Blocks the feature is placed on (surface) or in (veins)
+    #[serde(default)]
+    pub on: Vec<String>,
+    // World block height range
+    #[serde(default = "Feature::default_min_y")]
+    pub min_y: i32,
+    #[serde(default = "Feature::default_max_y")]
+    pub max_y: i32,
+}
+
+impl Feature {
+    // Furthest reach of features from their base, keeps deferred writes near
+    pub const MAX_RADIUS: i32 = SIZE_I32;
+    pub const MAX_HEIGHT: i32 = SIZE_I32 * 2;
+    pub const MAX_VEIN: u32 = (SIZE * SIZE) as u32;
+
+    fn default_min_y() -> i32 { i32::MIN }
+    fn default_max_y() -> i32 { i32::MAX }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let error = |e: String| Err(format!("Feature \"{}\": {e}", self.name));
+
+        if !self.per_chunk.is_finite() || self.per_chunk < 0.0 {
+            return error(format!("per_chunk {} must be a non-negative number", self.per_chunk));
+        }
+
+        if self.min_y > self.max_y {
+            return error(format!("min_y {} is above max_y {}", self.min_y, self.max_y));
+        }
+
+        let radius = match &self.kind {
+            FeatureKind::Tree { min_height, max_height, radius, .. } => {
+                if *min_height < 0 || min_height > max_height || *max_height > Self::MAX_HEIGHT {
+                    return error(format!(
+                        "tree height {min_height}..{max_height} must be within 0..{}", Self::MAX_HEIGHT
+                    ));
+                }
+
+                *radius
+            },
+            FeatureKind::Boulder { radius, .. } => *radius,
+            FeatureKind::Vein { size, .. } => {
+                if *size > Self::MAX_VEIN {
+                    return error(format!("vein size {size} exceeds {}", Self::MAX_VEIN));
+                }
+
+                0
+            }
+        };
+
+        match (0..=Self::MAX_RADIUS).contains(&radius) {
+            true => Ok(()),
+            false => error(format!("radius {radius} must be within 0..{}", Self::MAX_RADIUS))
+        }
+    }
+}
+
+#[derive(SpacetimeType, Debug, Clone, Copy, PartialEq, Eq)]
+// Which blocks a feature write may overwrite
+pub enum Replace {
+    Air,
+    Solid,
+}
+
+impl Replace {
+    pub fn allows(self, current: u16) -> bool {
+        match self {
+            Self::Air => current == 0,
+            Self::Solid => current != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Single block written by a feature (world coords)
+pub struct FeatureWrite {
+    pub pos: IVec3,
+    pub block: u16,
+    pub replace: Replace,
+}
+
+/// Feature write crossing a chunk border, kept so the
+/// target chunk gets it whenever it is (re)generated
+#[table(name = feature_write)]
+pub struct StFeatureWrite {
+    #[auto_inc]
+    #[primary_key]
+    id: u64,
+    // Target and source chunk keys, see `StIVec3::key`
+    #[index(btree)]
+    chunk: u64,
+    #[index(btree)]
+    source: u64,
+    pos: StIVec3,
+    block: u16,
+    replace: Replace,
+}
+
+impl FeatureWrite {
+    pub fn chunk(&self) -> IVec3 {
+        split_world(self.pos).0
+    }
+
+    /// Writes of other chunks into `chunk`
+    pub fn deferred(ctx: &ReducerContext, chunk: IVec3) -> Vec<FeatureWrite> {
+        ctx.db.feature_write().chunk().filter(StIVec3::from(chunk).key())
+            .map(|w| FeatureWrite { pos: w.pos.into(), block: w.block, replace: w.replace })
+            .collect()
+    }
+
+    /// Replace stored writes of `source` chunk
+    pub fn defer(ctx: &ReducerContext, source: IVec3, writes: &[FeatureWrite]) {
+        let source = StIVec3::from(source).key();
+        ctx.db.feature_write().source().delete(source);
+
+        for write in writes {
+            ctx.db.feature_write().insert(StFeatureWrite {
+                id: 0,
+                chunk: StIVec3::from(write.chunk()).key(),
+                source,
+                pos: write.pos.into(),
+                block: write.block,
+                replace: write.replace,
+            });
+        }
+    }
+
+    /// Apply write to its own chunk, returns true if block changed
+    pub fn apply(&self, chunk: &mut Chunk) -> bool {
+        let index = Chunk::block_index(split_world(self.pos).1);
+        if !self.replace.allows(chunk.get_block(index)) {
+            return false;
+        }
+
+        chunk.set_block(index, self.block);
+        true
+    }
+}
+
+// Feature with resolved block ids
+struct Placer<'a> {
+    feature: &'a Feature,
+    on: Vec<u16>,
+    blocks: Vec<u16>,
+    // Columns where feature biome rules pass
+    allowed: Vec<bool>,
+}
+
+/// Place features into generated chunk.
+///
+/// Writes inside the chunk are applied,
+/// writes to other chunks are returned to be deferred
+pub fn decorate(
+    chunk: &mut Chunk,
+    context: &Context,
+    handler: &BlocksHandler,
+    biomes: &[u8]
+) -> Vec<FeatureWrite> {
+    let position: IVec3 = chunk.position.into();
+    let origin = position * SIZE_I32;
+    let seed = context.seed;
+
+    let mut writes = Vec::new();
+    for (salt, feature) in context.config.features.iter().enumerate() {
+        // Skip chunks outside of feature height range
+        if origin.y + SIZE_I32 <= feature.min_y || origin.y > feature.max_y {
+            continue;
+        }
+
+        let find = |name: &String| handler.find_block(name).unwrap_or_else(|e| {
+            log::error!("Feature {}: {}", feature.name, e);
+            handler.unknown().id
+        });
+
+        let placer = Placer {
+            feature,
+            on: feature.on.iter().map(find).collect(),
+            blocks: match &feature.kind {
+                FeatureKind::Tree { trunk, leaves, .. } => vec![find(trunk), find(leaves)],
+                FeatureKind::Vein { block, .. } => vec![find(block)],
+                FeatureKind::Boulder { block, .. } => vec![find(block)],
+            },
+            allowed: biomes.iter().map(|&b| {
+                context.config.biomes.get(b as usize)
+                    .is_some_and(|biome| biome.features.contains(&feature.name))
+            }).collect()
+        };
+
+        let mut rng = ChunkRng::new(seed, position, salt as u64);
+        let attempts = feature.per_chunk.floor() as u32
+            + (rng.f32() < feature.per_chunk.fract()) as u32;
+
+        for _ in 0..attempts {
+            placer.place(chunk, origin, &mut rng, &mut writes);
+        }
+    }
+
+    let mut outside = Vec::new();
+    for write in writes.into_iter().filter(|w| context.config.bounds.contains_block(w.pos)) {
+        match write.chunk() == position {
+            true => { write.apply(chunk); },
+            false => outside.push(write)
+        }
+    }
+
+    outside
+}
+
+impl Placer<'_> {
+    // Highest solid block with air above in a chunk column
+    fn surface(chunk: &Chunk, x: i32, z: i32) -> Option<i32> {
+        (0..SIZE_I32).rev().find(|&y| {
+            let current = chunk.get_block(Chunk::block_index(ivec3(x, y, z)));
+            let above = match y + 1 < SIZE_I32 {
+                true => chunk.get_block(Chunk::block_index(ivec3(x, y + 1, z))),
+                false => 0
+            };
+
+            current != 0 && above == 0
+        })
+    }
+
+    fn place(&self, chunk: &Chunk, origin: IVec3, rng: &mut ChunkRng, writes: &mut Vec<FeatureWrite>) {
+        let (x, z) = (rng.i32(0..SIZE_I32), rng.i32(0..SIZE_I32));
+        if !self.allowed[(x + z * SIZE_I32) as usize] {
+            return;
+        }
+
+        match &self.feature.kind {
+            FeatureKind::Vein { size, .. } => {
+                let local = ivec3(x, rng.i32(0..SIZE_I32), z);
+                let block = chunk.get_block(Chunk::block_index(local));
+                if !self.on.is_empty() && !self.on.contains(&block) {
+                    return;
+                }
+
+                // Random walk from the start block
+                let mut pos = origin + local;
+                for _ in 0..*size {
+                    if self.in_range(pos.y) {
+                        writes.push(FeatureWrite { pos, block: self.blocks[0], replace: Replace::Solid });
+                    }
+
+                    pos += IVec3::AXES[rng.usize(0..3)] * if rng.bool() { 1 } else { -1 };
+                }
+            },
+            FeatureKind::Tree { min_height, max_height, radius, .. } => {
+                let Some(base) = self.base(chunk, origin, x, z) else { return };
+                let height = rng.i32(*min_height..=*max_height);
+
+                for y in 0..height {
+                    writes.push(FeatureWrite { pos: base + IVec3::Y * y, block: self.blocks[0], replace: Replace::Air });
+                }
+
+                // Leaves ball around trunk top
+                let top = base + IVec3::Y * height;
+                Self::sphere(top, *radius, |pos| {
+                    writes.push(FeatureWrite { pos, block: self.blocks[1], replace: Replace::Air });
+                });
+            },
+            FeatureKind::Boulder { radius, .. } => {
+                let Some(base) = self.base(chunk, origin, x, z) else { return };
+
+                Self::sphere(base, *radius, |pos| {
+                    writes.push(FeatureWrite { pos, block: self.blocks[0], replace: Replace::Air });
+                });
+            }
+        }
+    }
+
+    // First air block above a valid surface, world coords
+    fn base(&self, chunk: &Chunk, origin: IVec3, x: i32, z: i32) -> Option<IVec3> {
+        let y = Self::surface(chunk, x, z)?;
+        let block = chunk.get_block(Chunk::block_index(ivec3(x, y, z)));
+        if !self.on.is_empty() && !self.on.contains(&block) {
+            return None;
+        }
+
+        let base = origin + ivec3(x, y + 1, z);
+        self.in_range(base.y).then_some(base)
+    }
+
+    fn in_range(&self, y: i32) -> bool {
+        (self.feature.min_y..=self.feature.max_y).contains(&y)
+    }
+
+    fn sphere(center: IVec3, radius: i32, mut f: impl FnMut(IVec3)) {
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    let offset = ivec3(x, y, z);
+                    if offset.length_squared() <= radius * radius {
+                        f(center + offset);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,46 +1,269 @@
 use fastnoise_lite::*;
-use bevy_tasks::*;
 use std::sync::*;
 use super::*;
 
 
+#[derive(Debug, Clone, serde::Deserialize)]
+// Noise frequencies
+pub struct Terrain {
+    pub frequency: f32,
+    pub climate_frequency: f32,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Self { frequency: 0.01, climate_frequency: 0.002 }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+/// World generator config (`schema/gen.json`)
+pub struct Config {
+    #[serde(default)]
+    pub terrain: Terrain,
+    #[serde(default)]
+    pub density: Density,
+    #[serde(default)]
+    pub bounds: Bounds,
+    #[serde(default)]
+    pub loading: Loading,
+    #[serde(default)]
+    pub biomes: Vec<Biome>,
+    #[serde(default)]
+    pub features: Vec<Feature>,
+}
+
+impl Config {
+    // Feature rules and names referenced by biomes
+    fn validate_features(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for feature in &self.features {
+            feature.validate()?;
+            if !names.insert(feature.name.as_str()) {
+                return Err(format!("Feature \"{}\" is declared twice", feature.name));
+            }
+        }
+
+        for biome in &self.biomes {
+            if let Some(name) = biome.features.iter().find(|n| !names.contains(n.as_str())) {
+                return Err(format!("Biome \"{}\" uses undeclared feature \"{}\"", biome.name, name));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let file = SCHEME_DIR.get_file("gen.json")
+            .ok_or("Generator config file is not found")?;
+
+        let data = file.contents_utf8()
+            .ok_or("Generator config file is not utf-8")?;
+
+        // Empty config is allowed
+        if data.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let config: Self = serde_json::from_str(data)
+            .map_err(|e| format!("Generator config parse error: {e}"))?;
+
+        if config.biomes.len() > u8::MAX as usize {
+            return Err(format!("{} biomes declared, limit is {}", config.biomes.len(), u8::MAX));
+        }
+
+        config.density.validate()?;
+        config.bounds.validate()?;
+        config.validate_features()?;
+
+        Ok(config)
+    }
+}
+
+/// Seeded generation PRNG (SplitMix64).
+///
+/// All generation randomness goes through it, so the same
+/// world seed and chunk position always give the same chunk
+pub struct ChunkRng(u64);
+
+impl ChunkRng {
+    /// Mix world seed, chunk position and salt into a new stream
+    pub fn new(seed: i32, position: IVec3, salt: u64) -> Self {
+        let state = [position.x, position.y, position.z].into_iter()
+            .fold(Self::mix(seed as u64 ^ salt), |acc, v| Self::mix(acc ^ v as u64));
+
+        Self(state)
+    }
+
+    fn mix(mut v: u64) -> u64 {
+        v = (v ^ (v >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        v = (v ^ (v >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        v ^ (v >> 31)
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        Self::mix(self.0)
+    }
+
+    // Uniform value in 0..n
+    fn below(&mut self, n: u64) -> u64 {
+        ((self.u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    pub fn i32(&mut self, range: impl std::ops::RangeBounds<i32>) -> i32 {
+        use std::ops::Bound::*;
+        let start = match range.start_bound() {
+            Included(&s) => s as i64,
+            Excluded(&s) => s as i64 + 1,
+            Unbounded => i32::MIN as i64,
+        };
+        let end = match range.end_bound() {
+            Included(&e) => e as i64 + 1,
+            Excluded(&e) => e as i64,
+            Unbounded => i32::MAX as i64 + 1,
+        };
+
+        // Empty range gives its start
+        if start >= end {
+            return start as i32;
+        }
+
+        (start + self.below((end - start) as u64) as i64) as i32
+    }
+
+    pub fn usize(&mut self, range: std::ops::Range<usize>) -> usize {
+        if range.is_empty() {
+            return range.start;
+        }
+
+        range.start + self.below(range.len() as u64) as usize
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.u64() >> 63 == 1
+    }
+
+    /// Uniform value in 0..1
+    pub fn f32(&mut self) -> f32 {
+        (self.u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
 /// World gen context
-/// todo: load data from config
 pub struct Context {
+    pub seed: i32,
     noise: FastNoiseLite,
+    temperature: FastNoiseLite,
+    humidity: FastNoiseLite,
+    density: FastNoiseLite,
+    caves: FastNoiseLite,
+    pub config: Config,
 }
 
 static CONTEXT: OnceLock<RwLock<Context>> = OnceLock::new();
 
 impl Context {
-    pub fn new(seed: i32) -> Self {
-        Self { noise: FastNoiseLite::with_seed(seed) }
+    pub fn new(seed: i32, config: Config) -> Self {
+        let noise = |offset: i32, frequency: f32| {
+            let mut noise = FastNoiseLite::with_seed(seed.wrapping_add(offset));
+            noise.set_frequency(Some(frequency));
+            noise
+        };
+
+        Self {
+            seed,
+            noise: noise(0, config.terrain.frequency),
+            temperature: noise(1, config.terrain.climate_frequency),
+            humidity: noise(2, config.terrain.climate_frequency),
+            density: noise(3, config.density.frequency),
+            caves: noise(4, config.density.cave_frequency),
+            config
+        }
     }
 
-    pub fn init(seed: i32) {
-        let _ = CONTEXT.set(RwLock::new(Self::new(seed)));
+    pub fn init(seed: i32) -> Result<(), String> {
+        let config = Config::load()?;
+        let _ = CONTEXT.set(RwLock::new(Self::new(seed, config)));
+        Ok(())
     }
 
     pub fn get() -> &'static RwLock<Self> {
         CONTEXT.get().unwrap()
     }
+
+    pub fn set_seed(seed: i32) {
+        let mut access = Self::get().write().unwrap();
+        let config = std::mem::take(&mut access.config);
+        *access = Self::new(seed, config);
+    }
+
+    /// Temperature and humidity at world column, both from 0 to 1
+    pub fn climate(&self, x: i32, z: i32) -> Vec2 {
+        let sample = |noise: &FastNoiseLite| noise.get_noise_2d(x as f32, z as f32) * 0.5 + 0.5;
+        vec2(sample(&self.temperature), sample(&self.humidity))
+    }
+
+    /// Biome index in config at world column
+    pub fn biome_index(&self, x: i32, z: i32) -> usize {
+        let climate = self.climate(x, z);
+        self.config.biomes.iter()
+            .map(|b| b.climate_distance(climate))
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    /// Terrain height at world column,
+    /// biome heights are blended by climate distance
+    pub fn height_at(&self, x: i32, z: i32) -> i32 {
+        let climate = self.climate(x, z);
+        let (mut height, mut amplitude, mut total) = (0.0, 0.0, 0.0);
+
+        for biome in &self.config.biomes {
+            let weight = 1.0 / (biome.climate_distance(climate).powi(2) + 0.001);
+            height += biome.height * weight;
+            amplitude += biome.amplitude * weight;
+            total += weight;
+        }
+
+        if total == 0.0 {
+            return 0;
+        }
+
+        let noise = self.noise.get_noise_2d(x as f32, z as f32);
+        ((height + amplitude * noise) / total).round() as i32
+    }
+}
+
+// Biome blocks resolved to ids
+struct BiomeBlocks {
+    surface: u16,
+    filler: u16,
+    base: u16,
+}
+
+#[derive(Debug)]
+/// Generated chunk, feature writes to its neighbours
+/// and column biomes
+pub struct Generated {
+    pub chunk: Chunk,
+    pub outside: Vec<FeatureWrite>,
+    pub biomes: Vec<u8>,
 }
 
 #[derive(Debug)]
 pub struct Generator {
-    pub queue: Vec<IVec3>,
-    pub tasks: HashMap<IVec3, Task<Chunk>>
+    pub jobs: Runner<Generated>,
 }
 
 static VALUE: OnceLock<RwLock<Generator>> = OnceLock::new();
 
 impl Generator {
-    pub const MAX_TASKS: usize = 32;
-
     pub fn new() -> Self {
         Self {
-            queue: Vec::new(),
-            tasks: HashMap::new(),
+            jobs: Runner::new(),
         }
     }
 
@@ -52,36 +275,185 @@ impl Generator {
         VALUE.get().unwrap()
     }
 
-    pub async fn generate(position: IVec3) -> Chunk {
-        let _context = Context::get().read().unwrap();
-        let blocks = BlocksHandler::get().read().unwrap();
+    /// Generate chunk, output depends only on context and position
+    pub fn build(position: IVec3, context: &Context, blocks: &BlocksHandler) -> Generated {
         let mut chunk = Chunk::new(position);
 
-        // WIP: dynamic world generation
-        let range = 8;
-        if position.x > range 
-            || position.x < -range 
-            || position.y > range 
-            || position.y < -range 
-            || position.z > range 
-            || position.z < -range { return chunk; }
-
-        let vals = ["air", "dirt", "grass", "stone"];
-        
-        // todo: generate chunk
-        if position.y == 0 {
-            for i in 0..SIZE_P3 {
-                let name = fastrand::choice(vals).unwrap();
-                chunk.set_block(i, blocks.find_block(name));
+        let bounds = &context.config.bounds;
+        if !bounds.contains_chunk(position) {
+            return Generated { chunk, outside: Vec::new(), biomes: Vec::new() };
+        }
+
+        let find = |name: &String| blocks.find_block(name).unwrap_or_else(|e| {
+            log::error!("Generator: {}", e);
+            blocks.unknown().id
+        });
+
+        let biome_blocks = context.config.biomes.iter().map(|b| BiomeBlocks {
+            surface: find(&b.surface),
+            filler: find(&b.filler),
+            base: find(&b.base),
+        }).collect::<Vec<_>>();
+
+        let origin = position * SIZE_I32;
+        let density = &context.config.density;
+        let grid = DensityGrid::sample(&context.density, &context.caves, density, origin);
+        let mut biomes = Vec::with_capacity(SIZE.pow(2));
+
+        for z in 0..SIZE_I32 {
+            for x in 0..SIZE_I32 {
+                let (wx, wz) = (origin.x + x, origin.z + z);
+                let index = context.biome_index(wx, wz);
+                let height = context.height_at(wx, wz);
+                // No biome rows to point at without declared biomes
+                if !context.config.biomes.is_empty() {
+                    biomes.push(index as u8);
+                }
+
+                let (Some(biome), Some(ids)) = (context.config.biomes.get(index), biome_blocks.get(index)) else {
+                    continue;
+                };
+
+                // Walk down from above the chunk, counting solid blocks under air
+                let mut depth = i32::MAX / 2;
+                for y in (0..grid.height()).rev() {
+                    let local = ivec3(x, y, z);
+                    if !density.is_solid(height, origin.y + y, grid.get(local)) {
+                        depth = 0;
+                        continue;
+                    }
+
+                    depth += 1;
+                    if y >= SIZE_I32 || !bounds.contains_block(origin + local) {
+                        continue;
+                    }
+
+                    let id = match depth {
+                        1 => ids.surface,
+                        d if d <= biome.filler_depth + 1 => ids.filler,
+                        _ => ids.base
+                    };
+
+                    chunk.set_block(Chunk::block_index(local), id);
+                }
             }
         }
-        
-        chunk
+
+        // Decoration stage
+        let outside = decorate(&mut chunk, context, blocks, &biomes);
+        Generated { chunk, outside, biomes }
     }
 
     // Generator load stats
     pub fn load() -> (u32, u32) {
-        let access = Self::get().read().unwrap();
-        (access.tasks.len() as u32, access.queue.len() as u32)
+        Self::get().read().unwrap().jobs.load()
     }
-}
\ No newline at end of file
+}
+
+impl Job for Generator {
+    type Output = Generated;
+
+    const NAME: &'static str = "generator";
+    const TASK_COST: u64 = 2_000;
+    const STORE_COST: u64 = 300;
+    const RUNNING: ChunkState = ChunkState::Generating;
+    const DONE: ChunkState = ChunkState::Generated;
+
+    fn runner(&mut self) -> &mut Runner<Generated> {
+        &mut self.jobs
+    }
+
+    fn start(&mut self, _ctx: &ReducerContext, pos: IVec3) -> Option<impl FnOnce() -> Result<Generated, String> + Send + 'static> {
+        Some(move || {
+            let context = Context::get().read().map_err(|e| e.to_string())?;
+            let blocks = BlocksHandler::get().read().map_err(|e| e.to_string())?;
+            Ok(Self::build(pos, &context, &blocks))
+        })
+    }
+
+    fn complete(&mut self, ctx: &ReducerContext, pos: IVec3, generated: Generated) {
+        let Generated { mut chunk, outside, biomes } = generated;
+        if !biomes.is_empty() {
+            BiomeColumn::store(ctx, pos, biomes);
+        }
+
+        // Apply writes of already generated neighbours
+        for write in FeatureWrite::deferred(ctx, pos) {
+            write.apply(&mut chunk);
+        }
+
+        log::info!("Generated chunk: {}", pos);
+        LoadArea::insert(pos, Arc::new(ctx.db.chunk().insert(chunk)));
+        Mesher::get().write().unwrap().notify(pos);
+
+        // Neighbours that exist are edited now, all writes are kept
+        // for neighbours generated or regenerated later
+        FeatureWrite::defer(ctx, pos, &outside);
+        let mut edit = WorldEdit::new(ctx).unbounded();
+        for write in outside {
+            if ctx.db.chunk().key().find(StIVec3::from(write.chunk()).key()).is_none() {
+                continue;
+            }
+
+            if let Ok(current) = edit.get_block(write.pos)
+                && write.replace.allows(current) {
+                let _ = edit.set_block(write.pos, write.block);
+            }
+        }
+        edit.commit();
+    }
+
+    fn give_up(&mut self, _ctx: &ReducerContext, pos: IVec3) {
+        Mesher::get().write().unwrap().abandon(pos);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks() -> BlocksHandler {
+        let blocks = parse_blocks().unwrap().into_iter().enumerate()
+            .map(|(id, (name, model))| Block { id: id as u16, name, model });
+
+        BlocksHandler::from_blocks(blocks)
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let blocks = blocks();
+        let positions = [ivec3(0, 0, 0), ivec3(-3, 1, 5), ivec3(2, -1, -7)];
+
+        for position in positions {
+            let first = Generator::build(position, &Context::new(42, Config::load().unwrap()), &blocks);
+            let second = Generator::build(position, &Context::new(42, Config::load().unwrap()), &blocks);
+
+            assert_eq!(first.chunk.data, second.chunk.data, "chunk {position} differs");
+            assert_eq!(first.biomes, second.biomes);
+            assert_eq!(
+                first.outside.iter().map(|w| (w.pos, w.block)).collect::<Vec<_>>(),
+                second.outside.iter().map(|w| (w.pos, w.block)).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn rng_streams_depend_on_seed_and_position() {
+        let sample = |seed, pos| {
+            let mut rng = ChunkRng::new(seed, pos, 0);
+            (0..4).map(|_| rng.u64()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sample(1, IVec3::ZERO), sample(1, IVec3::ZERO));
+        assert_ne!(sample(1, IVec3::ZERO), sample(2, IVec3::ZERO));
+        assert_ne!(sample(1, IVec3::ZERO), sample(1, IVec3::X));
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn rng_empty_ranges_give_start() {
+        let mut rng = ChunkRng::new(1, IVec3::ZERO, 0);
+        assert_eq!(rng.i32(5..5), 5);
+        assert_eq!(rng.i32(7..=3), 7);
+        assert_eq!(rng.usize(2..2), 2);
+    }
+}
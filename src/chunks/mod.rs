@@ -3,8 +3,7 @@ use std::{
     sync::*,
 };
 use include_directory::{include_directory, Dir};
-use spacetimedb::{table, ReducerContext, Table};
-use bevy_tasks::*;
+use spacetimedb::{table, ReducerContext, Table, Timestamp};
 
 mod blocks;
 pub use blocks::*;
@@ -12,7 +11,34 @@ pub use blocks::*;
 mod generate;
 pub use generate::*;
 
-use super::math::*;
+mod features;
+pub use features::*;
+
+mod biomes;
+pub use biomes::*;
+
+mod density;
+pub use density::*;
+
+mod bounds;
+pub use bounds::*;
+
+mod edit;
+pub use edit::*;
+
+mod schematics;
+pub use schematics::*;
+
+mod status;
+pub use status::*;
+
+use super::{
+    math::*,
+    mesher::{Mesh, Mesher},
+    budget::Budget,
+    metrics::Latency,
+    jobs::{self, Job, Runner},
+};
 
 pub(super) static SCHEME_DIR: Dir<'static> = include_directory!("./schema");
 
@@ -54,6 +80,17 @@ impl LoadArea {
 
         access.get(pos).cloned()
     }
+
+    pub fn len() -> usize {
+        Self::value().0.read().unwrap().len()
+    }
+
+    pub fn positions() -> Vec<IVec3> {
+        let value = Self::value();
+        let access = value.0.read().unwrap();
+
+        access.keys().copied().collect()
+    }
 }
 
 // Chunk constants
@@ -69,6 +106,45 @@ pub const HALF_BYTE: usize = BYTE / 2;
 // How much bytes
 pub const BUF_SIZE: usize = SIZE_P3 * BLOCK_SIZE / BYTE;
 
+/// Bytes to pack `count` 12-bit values
+pub fn packed_size(count: usize) -> usize {
+    (count * BLOCK_SIZE).div_ceil(BYTE)
+}
+
+pub fn get_packed(data: &[u8], index: usize) -> u16 {
+    let i = index * BLOCK_SIZE / BYTE;
+    let (a, b) = match index.is_multiple_of(2) {
+        // First and second bytes
+        // 0110_0001 1001_0010 1110_1000 => 0110_0001 and 1001    
+        true => (data[i] as u16, (data[i+1] >> HALF_BYTE) as u16),
+        // Second and third bytes
+        // 0110_0001 1001_0010 1110_1000 => 0010 and 1110_1000
+        false => ((data[i] << HALF_BYTE) as u16, data[i+1] as u16)
+    };
+    
+    a << HALF_BYTE | b
+}
+
+pub fn set_packed(data: &mut [u8], index: usize, value: u16) {
+    let i = index * BLOCK_SIZE / BYTE;
+    match index.is_multiple_of(2) {
+        true => {
+            // 0000_0101_1100_0011 => 0101_1100 and 0011
+            let (a, b) = ((value >> HALF_BYTE) as u8, (value & 0b1111) as u8);
+
+            data[i] = a;
+            data[i+1] = (data[i+1] & 0b0000_1111) | (b << HALF_BYTE);
+        },
+        false => {
+            // 0000_0101_1100_0011 => 0101 and 1100_0011
+            let (a, b) = ((value >> BYTE) as u8, (value & 0b1111_1111) as u8);
+
+            data[i] = (data[i] & 0b1111_0000) | a;
+            data[i+1] = b;
+        }
+    }
+}
+
 #[table(name = chunk, public)]
 #[derive(Debug)]
 pub struct Chunk {
@@ -76,7 +152,9 @@ pub struct Chunk {
     #[primary_key]
     id: u64,
 
+    // Packed position, see `StIVec3::key`
     #[unique]
+    pub key: u64,
     pub position: StIVec3,
     // Compressed chunk data
     pub data: Vec<u8>
@@ -84,43 +162,35 @@ pub struct Chunk {
 
 impl Chunk {
     pub fn new(position: IVec3) -> Self {
-        let position = position.into();
+        let position: StIVec3 = position.into();
         let data = std::iter::repeat_n(0, BUF_SIZE).collect();
-        Self { id: 0, position, data }
+        Self { id: 0, key: position.key(), position, data }
+    }
+
+    /// Uniform air, its id is always 0
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|b| *b == 0)
     }
 
     pub fn get_block(&self, index: usize) -> u16 {
-        let i = index * BLOCK_SIZE / BYTE;
-        let (a, b) = match index % 2 == 0 {
-            // First and second bytes
-            // 0110_0001 1001_0010 1110_1000 => 0110_0001 and 1001    
-            true => (self.data[i] as u16, (self.data[i+1] >> HALF_BYTE) as u16),
-            // Second and third bytes
-            // 0110_0001 1001_0010 1110_1000 => 0010 and 1110_1000
-            false => ((self.data[i] << HALF_BYTE) as u16, self.data[i+1] as u16)
-        };
-        
-        a << HALF_BYTE | b
+        get_packed(&self.data, index)
     }
 
     pub fn set_block(&mut self, index: usize, value: u16) {
-        let i = index * BLOCK_SIZE / BYTE;
-        match index % 2 == 0 {
-            true => {
-                // 0000_0101_1100_0011 => 0101_1100 and 0011
-                let (a, b) = ((value >> HALF_BYTE) as u8, (value & 0b1111) as u8);
-
-                self.data[i] = a;
-                self.data[i+1] = (self.data[i+1] & 0b0000_1111) | (b << HALF_BYTE);
-            },
-            false => {
-                // 0000_0101_1100_0011 => 0101 and 1100_0011
-                let (a, b) = ((value >> BYTE) as u8, (value & 0b1111_1111) as u8);
-
-                self.data[i] = (self.data[i] & 0b1111_0000) | a;
-                self.data[i+1] = b;
+        set_packed(&mut self.data, index, value)
+    }
+
+    /// Replace block ids, returns true if any block changed
+    pub fn remap_blocks(&mut self, remap: &HashMap<u16, u16>) -> bool {
+        let mut changed = false;
+        for i in 0..SIZE_P3 {
+            if let Some(&new) = remap.get(&self.get_block(i)) {
+                self.set_block(i, new);
+                changed = true;
             }
         }
+
+        changed
     }
 
     /// XZY coord system
@@ -151,31 +221,29 @@ impl ChunksRefs {
         IVec3::Z,     // back
     ];
 
-    // Helper function: create an array from Vec
-    fn to_array<T: std::fmt::Debug, const N: usize>(data: Vec<T>) -> [T; N] {
-        data.try_into().expect("Wrong size")
-    }
-
     // Helper function: get chunk from BD
     pub fn get_chunk(position: IVec3) -> Option<Arc<Chunk>> {
         LoadArea::get(&position)
     }
 
-    // Create chunk refs
+    // Create chunk refs, missing neighbours are treated as air
     pub fn new(pos: IVec3) -> Option<Self> {
-        let mut data = Vec::<Arc<Chunk>>::with_capacity(7);
-        for n in 0..7 {
-            data.push(Self::get_chunk(pos + ChunksRefs::OFFSETS[n])?)
-        }
+        let current = Self::get_chunk(pos)?;
 
-        Some(Self(Self::to_array(data)))
+        Some(Self(std::array::from_fn(|i| match i {
+            0 => current.clone(),
+            _ => {
+                let n = pos + Self::OFFSETS[i];
+                Self::get_chunk(n).unwrap_or_else(|| Arc::new(Chunk::new(n)))
+            }
+        })))
     }
 
-    fn offset_index(v: IVec3) -> usize {
-        Self::OFFSETS.iter().position(|p| p==&v).unwrap()
+    fn offset_index(v: IVec3) -> Option<usize> {
+        Self::OFFSETS.iter().position(|p| p==&v)
     }
 
-    fn chunk_index(x: usize, y: usize, z: usize) -> usize {
+    fn chunk_index(x: usize, y: usize, z: usize) -> Option<usize> {
         let (cx, cy, cz) = (
             (x / SIZE) as i32,
             (y / SIZE) as i32, 
@@ -195,84 +263,252 @@ impl ChunksRefs {
         Chunk::block_index(IVec3::new(bx, by, bz))
     }
 
-    pub fn get_block(&self, pos: IVec3) -> u16 {
-        let x = (pos.x + SIZE_I32) as usize;
-        let y = (pos.y + SIZE_I32) as usize;
-        let z = (pos.z + SIZE_I32) as usize;
-        let chunk = Self::chunk_index(x, y, z);
+    // Current chunk position
+    pub fn position(&self) -> IVec3 {
+        self.0[0].position.into()
+    }
+
+    /// Block at position local to current chunk,
+    /// `None` outside of it and its face neighbours
+    pub fn get_block(&self, pos: IVec3) -> Option<u16> {
+        let shifted = pos + IVec3::splat(SIZE_I32);
+        if shifted.min_element() < 0 || shifted.max_element() >= SIZE_I32 * 3 {
+            return None;
+        }
+
+        let (x, y, z) = (shifted.x as usize, shifted.y as usize, shifted.z as usize);
+        let chunk = Self::chunk_index(x, y, z)?;
         let block = Self::block_index(x, y, z);
 
-        self.0[chunk].get_block(block)
+        Some(self.0[chunk].get_block(block))
     }
 }
 
-pub fn proceed_generator(ctx: &ReducerContext) {
-    let task_pool = AsyncComputeTaskPool::get();
-    let mut generator = Generator::get().write().unwrap();
+/// Store finished chunks and spawn new tasks, returns generated chunks count
+pub fn proceed_generator(ctx: &ReducerContext, budget: &mut Budget) -> u32 {
+    jobs::proceed(&mut *Generator::get().write().unwrap(), ctx, budget)
+}
+
+// Parse and validate blocks schema file
+pub fn parse_blocks() -> Result<Vec<(String, ModelType)>, Vec<SchemaError>> {
+    let blocks_file = SCHEME_DIR.get_file("blocks.json")
+        .ok_or(vec![SchemaError::NotFound])?;
+
+    let data = blocks_file.contents_utf8()
+        .ok_or(vec![SchemaError::Parse("file is not utf-8".into())])?;
+
+    parse_blocks_json(data)
+}
 
-    for (pos, task) in generator.tasks.drain().collect::<Vec<_>>() {
-        if !task.is_finished() {
-            generator.tasks.insert(pos, task);
+/// Parse and validate blocks schema, same format as `blocks.json`
+pub fn parse_blocks_json(data: &str) -> Result<Vec<(String, ModelType)>, Vec<SchemaError>> {
+    let blocks: Vec<(String, ModelType)> = serde_json::from_str(data)
+        .map_err(|e| vec![SchemaError::Parse(e.to_string())])?;
+
+    validate_blocks(&blocks)?;
+    Ok(blocks)
+}
+
+// Block name to id
+type BlockIds = HashMap<String, u16>;
+
+/// Assign block ids, keeping every id in the `block_id` registry.
+///
+/// New blocks take ids after the highest one ever used. Returns ids
+/// of all registered names and remap of stored ids that must change
+fn assign_block_ids(
+    registered: &BlockIds,
+    blocks: &[(String, ModelType)]
+) -> Result<(BlockIds, HashMap<u16, u16>), Vec<SchemaError>> {
+    let mut ids = registered.clone();
+    let mut remap = HashMap::new();
+
+    let mut next = ids.values().max().map_or(0, |id| *id as usize + 1);
+    for (name, _) in blocks {
+        if ids.contains_key(name) {
             continue;
         }
 
-        let chunk = block_on(task);
-        log::info!("Generated chunk: {}", pos);
-        LoadArea::insert(pos, Arc::new(ctx.db.chunk().insert(chunk)));
+        if next >= MAX_BLOCKS {
+            return Err(vec![SchemaError::IdsExhausted]);
+        }
+
+        ids.insert(name.clone(), next as u16);
+        next += 1;
     }
 
-    let l = generator.queue.len().min(Generator::MAX_TASKS - generator.tasks.len());
-    for pos in generator.queue.drain(0..l).collect::<Vec<IVec3>>() {
-        let task = task_pool.spawn(Generator::generate(pos));
-        generator.tasks.insert(pos, task);
+    // Air is always id 0, swap with the current owner
+    if let Some(&air) = ids.get("air") && air != 0 {
+        if let Some(owner) = ids.iter().find(|(_, id)| **id == 0).map(|(n, _)| n.clone()) {
+            ids.insert(owner, air);
+            remap.insert(0, air);
+        }
+
+        ids.insert("air".to_string(), 0);
+        remap.insert(air, 0);
     }
+
+    Ok((ids, remap))
+}
+
+// Rewrite stored chunks after block ids change
+fn migrate_chunks(ctx: &ReducerContext, remap: &HashMap<u16, u16>) {
+    if remap.is_empty() {
+        return;
+    }
+
+    log::info!("Block ids changed, migrating chunks: {:?}", remap);
+    let mut mesher = Mesher::get().write().unwrap();
+    for mut chunk in ctx.db.chunk().iter() {
+        if chunk.remap_blocks(remap) {
+            let chunk = ctx.db.chunk().id().update(chunk);
+            let pos = chunk.position.into();
+
+            // Meshes store block ids too, unloaded ones are rebuilt on load
+            match LoadArea::get(&pos).is_some() {
+                true => {
+                    LoadArea::insert(pos, Arc::new(chunk));
+                    mesher.jobs.queue.push(pos);
+                },
+                false => Mesh::remove(ctx, pos)
+            }
+        }
+    }
+}
+
+// Join schema errors into reducer error
+pub fn schema_errors(errors: Vec<SchemaError>) -> String {
+    for e in &errors {
+        log::error!("{}", e);
+    }
+
+    errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ")
 }
 
-pub fn init_blocks(ctx: &ReducerContext) {
-    // clear blocks data
+// Write blocks schema into `block` table
+fn update_blocks(ctx: &ReducerContext, blocks: Vec<(String, ModelType)>) -> Result<(), Vec<SchemaError>> {
+    // Registry starts from blocks stored before it existed
+    let mut registered = ctx.db.block_id().iter()
+        .map(|b| (b.name, b.id))
+        .collect::<HashMap<_, _>>();
+    if registered.is_empty() {
+        registered.extend(ctx.db.block().iter().map(|b| (b.name, b.id)));
+    }
+
+    let (ids, remap) = assign_block_ids(&registered, &blocks)?;
+    migrate_chunks(ctx, &remap);
+
+    for entry in ctx.db.block_id().iter() {
+        ctx.db.block_id().name().delete(&entry.name);
+    }
+
+    for (name, &id) in &ids {
+        ctx.db.block_id().insert(BlockId { name: name.clone(), id });
+    }
+
+    // clear blocks data, removed ids resolve to the placeholder
     for block in ctx.db.block().iter() {
         ctx.db.block().id().delete(block.id);
     }
 
-    let blocks_file = SCHEME_DIR.get_file("blocks.json")
-        .expect("Blocks data file is not found");
-    
-    let blocks: Vec<(String, ModelType)> = blocks_file.contents_utf8()
-        .and_then(|data| serde_json::from_str(data).ok())
-        .expect("Blocks data file parse error");
-
-    for (id, (name, model)) in blocks.into_iter().enumerate() {
-        let id = id as u16;
+    for (name, model) in blocks {
+        let id = ids[&name];
         ctx.db.block().insert(Block { id, name, model });
     }
 
+    Ok(())
+}
+
+pub fn init_blocks(ctx: &ReducerContext) -> Result<(), Vec<SchemaError>> {
+    update_blocks(ctx, parse_blocks()?)?;
     BlocksHandler::init(ctx);
+    Ok(())
 }
 
-// Init main values and world area
-pub fn setup(ctx: &ReducerContext) {
-    Context::init(0);
-    Generator::init();
-    LoadArea::init();
-    init_blocks(ctx);
+/// Replace block definitions at runtime, from `json`
+/// if given or the bundled `blocks.json` otherwise
+pub fn reload_blocks(ctx: &ReducerContext, json: Option<&str>) -> Result<(), Vec<SchemaError>> {
+    let blocks = match json {
+        Some(data) => parse_blocks_json(data)?,
+        None => parse_blocks()?
+    };
+
+    update_blocks(ctx, blocks)?;
+    BlocksHandler::reload(ctx);
+    Ok(())
+}
 
-    let range = 10;
-    let l = ((range*2)+1) as usize;
-    let mut area = Vec::with_capacity(l.pow(3));
+// Max chunks touched by one area operation
+pub const MAX_AREA_CHUNKS: usize = 4096;
+
+/// All chunk positions in a box, corners included
+pub fn area(min: IVec3, max: IVec3) -> Result<Vec<IVec3>, String> {
+    let (min, max) = (min.min(max), min.max(max));
+    let size = (max - min + IVec3::ONE).as_uvec3();
+    let volume = size.x as usize * size.y as usize * size.z as usize;
+
+    if volume > MAX_AREA_CHUNKS {
+        return Err(format!("Area of {volume} chunks exceeds limit of {MAX_AREA_CHUNKS}"));
+    }
 
-    for x in -range..=range {
-        for y in -range..=range {
-            for z in -range..=range {
+    let mut area = Vec::with_capacity(volume);
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
                 area.push(ivec3(x, y, z));
             }
         }
     }
 
+    Ok(area)
+}
+
+/// Drop chunks in a box and queue them for generation again
+pub fn regenerate(ctx: &ReducerContext, min: IVec3, max: IVec3) -> Result<usize, String> {
+    // Chunks outside bounds are never stored, their keys may alias
+    let bounds = Context::get().read().unwrap().config.bounds.clone();
+    let positions = area(min, max)?.into_iter()
+        .filter(|pos| bounds.contains_chunk(*pos))
+        .collect::<Vec<_>>();
+
     let mut generator = Generator::get().write().unwrap();
-    let mut mesher = super::mesher::Mesher::get().write().unwrap();
-    
-    for pos in area {
-        generator.queue.push(pos);
-        mesher.queue.push(pos);
+    let mut mesher = Mesher::get().write().unwrap();
+
+    for &pos in &positions {
+        ctx.db.chunk().key().delete(StIVec3::from(pos).key());
+        Mesh::remove(ctx, pos);
+        LoadArea::remove(&pos);
+
+        generator.jobs.cancel(&pos);
+        mesher.cancel(&pos);
     }
+
+    generator.jobs.queue.extend(positions.iter().copied());
+    for &pos in &positions {
+        Latency::enqueued(pos, ctx.timestamp);
+        ChunkStatus::set(ctx, pos, ChunkState::Queued);
+    }
+
+    // Border neighbours share faces with the area, remesh them too
+    let remesh = area(min.min(max) - IVec3::ONE, min.max(max) + IVec3::ONE)
+        .unwrap_or_else(|_| positions.clone());
+    mesher.jobs.queue.extend(remesh);
+
+    Ok(positions.len())
+}
+
+// Init main values and world area
+pub fn setup(ctx: &ReducerContext) -> Result<(), String> {
+    Context::init(0)?;
+    init_biomes(ctx);
+    Generator::init();
+    Evictor::init();
+    LoadArea::init();
+
+    init_blocks(ctx).map_err(schema_errors)?;
+
+    let radius = Context::get().read().unwrap().config.loading.spawn_radius;
+    load_around(ctx, IVec3::ZERO, radius);
+
+    Ok(())
 }
\ No newline at end of file
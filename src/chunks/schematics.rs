@@ -0,0 +1,115 @@
+use spacetimedb::{Identity, Timestamp};
+use super::*;
+
+/// Portable block structure.
+///
+/// Blocks are stored as packed 12-bit palette indices (XZY order),
+/// palette holds block names, so schematics survive block id changes
+#[table(name = schematic, public)]
+#[derive(Debug)]
+pub struct Schematic {
+    #[auto_inc]
+    #[primary_key]
+    id: u64,
+    #[unique]
+    pub name: String,
+    pub size: StIVec3,
+    pub palette: Vec<String>,
+    pub data: Vec<u8>,
+    pub author: Identity,
+    pub created: Timestamp,
+}
+
+impl Schematic {
+    fn index(size: IVec3, pos: IVec3) -> usize {
+        (pos.x + pos.z * size.x + pos.y * size.x * size.z) as usize
+    }
+
+    /// Read blocks in a box (world coords) into a schematic
+    pub fn save(
+        ctx: &ReducerContext,
+        name: String,
+        min: IVec3,
+        max: IVec3
+    ) -> Result<Self, String> {
+        let (min, max) = (min.min(max), min.max(max));
+        let size = edit_size(min, max)?;
+        let volume = (size.x * size.y * size.z) as usize;
+
+        let handler = BlocksHandler::get().read().unwrap();
+        let mut edit = WorldEdit::new(ctx);
+        let mut palette = Vec::<String>::new();
+        let mut indices = HashMap::<u16, u16>::new();
+        let mut data = vec![0; packed_size(volume)];
+
+        for_each_block(min, max, |pos| {
+            let id = edit.get_block(pos)?;
+            let index = match indices.get(&id) {
+                Some(index) => *index,
+                None => {
+                    let block = handler.block(id).unwrap_or_else(|_| handler.unknown());
+                    palette.push(block.name.clone());
+                    indices.insert(id, palette.len() as u16 - 1);
+                    palette.len() as u16 - 1
+                }
+            };
+
+            set_packed(&mut data, Self::index(size, pos - min), index);
+            Ok(())
+        })?;
+
+        Ok(Self {
+            id: 0,
+            name,
+            size: size.into(),
+            palette,
+            data,
+            author: ctx.sender,
+            created: ctx.timestamp,
+        })
+    }
+
+    /// Write schematic blocks at `pos` (lowest corner),
+    /// rotated by `rotation` quarter turns around Y
+    pub fn paste(
+        &self,
+        ctx: &ReducerContext,
+        pos: IVec3,
+        rotation: u8,
+        include_air: bool
+    ) -> Result<usize, String> {
+        let size = IVec3::from(self.size);
+        edit_size(IVec3::ZERO, size - IVec3::ONE)?;
+
+        let handler = BlocksHandler::get().read().unwrap();
+        let ids = self.palette.iter().map(|name| {
+            handler.find_block(name).unwrap_or_else(|e| {
+                log::warn!("Schematic {}: {}", self.name, e);
+                handler.unknown().id
+            })
+        }).collect::<Vec<_>>();
+
+        let mut edit = WorldEdit::new(ctx);
+        for_each_block(IVec3::ZERO, size - IVec3::ONE, |local| {
+            let id = ids[get_packed(&self.data, Self::index(size, local)) as usize];
+            if id == 0 && !include_air {
+                return Ok(());
+            }
+
+            edit.set_block(pos + Self::rotate(local, size, rotation), id)
+        })?;
+
+        Ok(edit.commit())
+    }
+
+    // Rotate local position around Y, keeping it inside the rotated box
+    fn rotate(local: IVec3, size: IVec3, rotation: u8) -> IVec3 {
+        let (x, y, z) = (local.x, local.y, local.z);
+        match rotation % 4 {
+            0 => ivec3(x, y, z),
+            1 => ivec3(size.z - 1 - z, y, x),
+            2 => ivec3(size.x - 1 - x, y, size.z - 1 - z),
+            _ => ivec3(z, y, size.x - 1 - x),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use spacetimedb::SpacetimeType;
+use super::*;
+
+/// Chunk lifecycle state
+#[derive(SpacetimeType, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkState {
+    Queued,
+    Generating,
+    Generated,
+    // Mesh waits for neighbours generation
+    Waiting,
+    Meshing,
+    Meshed,
+    // Mesh skipped: uniform air or solid enclosed by solid neighbours
+    Empty,
+    Occluded,
+    // Job given up, see `job_failure`
+    Failed,
+    Unloaded,
+}
+
+/// Chunk state for clients loading placeholders
+#[table(name = chunk_status, public)]
+pub struct ChunkStatus {
+    // Packed position, see `StIVec3::key`
+    #[primary_key]
+    key: u64,
+    position: StIVec3,
+    state: ChunkState,
+    // First seen and last state change
+    created: Timestamp,
+    changed: Timestamp,
+}
+
+impl ChunkStatus {
+    pub fn set(ctx: &ReducerContext, pos: IVec3, state: ChunkState) {
+        let position = StIVec3::from(pos);
+        let key = position.key();
+
+        match ctx.db.chunk_status().key().find(key) {
+            Some(status) if status.state == state => {},
+            Some(status) => {
+                ctx.db.chunk_status().key().update(ChunkStatus { state, changed: ctx.timestamp, ..status });
+            },
+            None => {
+                ctx.db.chunk_status().insert(ChunkStatus {
+                    key,
+                    position,
+                    state,
+                    created: ctx.timestamp,
+                    changed: ctx.timestamp,
+                });
+            }
+        }
+    }
+}
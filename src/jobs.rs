@@ -0,0 +1,336 @@
+use std::collections::*;
+use bevy_tasks::{block_on, AsyncComputeTaskPool, Task};
+use spacetimedb::{table, ReducerContext, Table, TimeDuration, Timestamp};
+
+use super::{
+    math::*,
+    queue::ChunkQueue,
+    budget::Budget,
+    chunks::{ChunkStatus, ChunkState},
+};
+
+// Attempts before a failing job is given up
+pub const MAX_ATTEMPTS: u32 = 5;
+// Delay before the first retry, doubled on every next one, microseconds
+pub const BACKOFF: i64 = 500_000;
+
+/// Chunk work of one kind run on the task pool.
+///
+/// `proceed` pops queued positions, `start` prepares the work
+/// inside the reducer, it runs on the pool and `complete`
+/// applies the output in a later tick. `INLINE` jobs run
+/// and complete their work in the same tick instead
+pub trait Job {
+    type Output: Send + 'static;
+
+    // Name in `job` table
+    const NAME: &'static str;
+    // Estimated costs of spawning and storing work, microseconds
+    const TASK_COST: u64;
+    const STORE_COST: u64;
+    // Estimated cost of a position `start` skips
+    const SKIP_COST: u64 = 0;
+    // Work too cheap for a pool task, `TASK_COST` covers all of it
+    const INLINE: bool = false;
+    // Chunk states while work runs and after it is applied,
+    // inline work is never left running
+    const RUNNING: ChunkState;
+    const DONE: ChunkState;
+
+    fn runner(&mut self) -> &mut Runner<Self::Output>;
+
+    /// Work for popped position, `None` skips it.
+    ///
+    /// Work reports failures as `Err`: the module runs with
+    /// `panic = "abort"`, so a panic aborts the whole tick
+    fn start(
+        &mut self,
+        ctx: &ReducerContext,
+        pos: IVec3
+    ) -> Option<impl FnOnce() -> Result<Self::Output, String> + Send + 'static>;
+
+    fn complete(&mut self, ctx: &ReducerContext, pos: IVec3, output: Self::Output);
+
+    /// Work at position was given up after `MAX_ATTEMPTS`
+    fn give_up(&mut self, _ctx: &ReducerContext, _pos: IVec3) {}
+}
+
+/// Queued and running work of a job
+pub struct Runner<T> {
+    pub queue: ChunkQueue,
+    tasks: HashMap<IVec3, Task<Result<T, String>>>,
+    // Failed attempts of retried positions
+    attempts: HashMap<IVec3, u32>,
+    // Failed positions waiting to be queued again
+    backoff: HashMap<IVec3, Timestamp>,
+    given_up: Vec<IVec3>,
+    completed: u64,
+    retried: u64,
+    failed: u64,
+    cancelled: u64,
+}
+
+impl<T: Send + 'static> Runner<T> {
+    pub fn new() -> Self {
+        Self {
+            queue: ChunkQueue::new(),
+            tasks: HashMap::new(),
+            attempts: HashMap::new(),
+            backoff: HashMap::new(),
+            given_up: Vec::new(),
+            completed: 0,
+            retried: 0,
+            failed: 0,
+            cancelled: 0,
+        }
+    }
+
+    // Running and queued counts
+    pub fn load(&self) -> (u32, u32) {
+        (self.tasks.len() as u32, self.queue.len() as u32)
+    }
+
+    /// Queued, running or waiting for retry
+    pub fn is_pending(&self, pos: &IVec3) -> bool {
+        self.queue.contains(pos) || self.tasks.contains_key(pos) || self.backoff.contains_key(pos)
+    }
+
+    /// Queued, running and waiting for retry positions
+    pub fn pending(&self) -> Vec<IVec3> {
+        self.queue.iter()
+            .chain(self.tasks.keys())
+            .chain(self.backoff.keys())
+            .copied()
+            .collect()
+    }
+
+    /// Drop queued or running work, returns false if there was none
+    pub fn cancel(&mut self, pos: &IVec3) -> bool {
+        self.attempts.remove(pos);
+
+        // Dropping task cancels it
+        let cancelled = self.queue.remove(pos)
+            | self.tasks.remove(pos).is_some()
+            | self.backoff.remove(pos).is_some();
+        if cancelled {
+            self.cancelled += 1;
+        }
+
+        cancelled
+    }
+
+    fn spawn(&mut self, pos: IVec3, work: impl FnOnce() -> Result<T, String> + Send + 'static) {
+        let task = AsyncComputeTaskPool::get().spawn(async move { work() });
+        self.tasks.insert(pos, task);
+    }
+
+    // Queue failed positions whose backoff has passed
+    fn retry_due(&mut self, now: Timestamp) {
+        let due = self.backoff.iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+
+        for pos in due {
+            self.backoff.remove(&pos);
+            self.queue.push(pos);
+            self.retried += 1;
+        }
+    }
+
+    // Take finished output of pool work
+    fn next_finished(&mut self, ctx: &ReducerContext, name: &str) -> Option<(IVec3, T)> {
+        loop {
+            let pos = self.tasks.iter().find(|(_, t)| t.is_finished()).map(|(p, _)| *p)?;
+            let task = self.tasks.remove(&pos).unwrap();
+
+            if let Some(output) = self.finish(ctx, name, pos, block_on(task)) {
+                return Some((pos, output));
+            }
+        }
+    }
+
+    // Output of succeeded work, failed work is retried with backoff until `MAX_ATTEMPTS`
+    fn finish(&mut self, ctx: &ReducerContext, name: &str, pos: IVec3, result: Result<T, String>) -> Option<T> {
+        let error = match result {
+            Ok(output) => {
+                if self.attempts.remove(&pos).is_some() {
+                    JobFailure::clear(ctx, name, pos);
+                }
+
+                self.completed += 1;
+                return Some(output);
+            },
+            Err(e) => e
+        };
+
+        let attempts = self.attempts.entry(pos).or_default();
+        *attempts += 1;
+        let attempts = *attempts;
+        log::error!("{} job at {} failed, attempt {}: {}", name, pos, attempts, error);
+
+        let retry_at = match attempts < MAX_ATTEMPTS {
+            true => {
+                let delay = TimeDuration::from_micros(BACKOFF << (attempts - 1));
+                let at = ctx.timestamp + delay;
+                self.backoff.insert(pos, at);
+                Some(at)
+            },
+            false => {
+                self.attempts.remove(&pos);
+                self.given_up.push(pos);
+                self.failed += 1;
+                None
+            }
+        };
+
+        JobFailure::record(ctx, JobFailure {
+            id: 0,
+            key: StIVec3::from(pos).key(),
+            job: name.to_string(),
+            position: pos.into(),
+            error,
+            attempts,
+            retry_at,
+        });
+        None
+    }
+}
+
+// Tasks are opaque, show counts only
+impl<T> std::fmt::Debug for Runner<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Runner")
+            .field("queued", &self.queue.len())
+            .field("running", &self.tasks.len())
+            .field("completed", &self.completed)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl<T: Send + 'static> Default for Runner<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Last error of a failed chunk job,
+/// removed once the job succeeds
+#[table(name = job_failure, public)]
+pub struct JobFailure {
+    #[auto_inc]
+    #[primary_key]
+    id: u64,
+    // Packed position, see `StIVec3::key`
+    #[index(btree)]
+    key: u64,
+    job: String,
+    position: StIVec3,
+    error: String,
+    attempts: u32,
+    // Next attempt, none when the job was given up
+    retry_at: Option<Timestamp>,
+}
+
+impl JobFailure {
+    fn find(ctx: &ReducerContext, job: &str, key: u64) -> Option<JobFailure> {
+        ctx.db.job_failure().key().filter(key).find(|f| f.job == job)
+    }
+
+    fn record(ctx: &ReducerContext, mut failure: JobFailure) {
+        match Self::find(ctx, &failure.job, failure.key) {
+            Some(old) => {
+                failure.id = old.id;
+                ctx.db.job_failure().id().update(failure);
+            },
+            None => { ctx.db.job_failure().insert(failure); }
+        }
+    }
+
+    fn clear(ctx: &ReducerContext, job: &str, pos: IVec3) {
+        if let Some(failure) = Self::find(ctx, job, StIVec3::from(pos).key()) {
+            ctx.db.job_failure().id().delete(failure.id);
+        }
+    }
+}
+
+/// Job counters for clients and admins
+#[table(name = job, public)]
+pub struct JobStatus {
+    #[primary_key]
+    name: String,
+    running: u32,
+    queued: u32,
+    completed: u64,
+    retried: u64,
+    failed: u64,
+    cancelled: u64,
+}
+
+impl JobStatus {
+    fn store<T: Send + 'static>(ctx: &ReducerContext, name: &str, runner: &Runner<T>) {
+        let (running, queued) = runner.load();
+        let status = JobStatus {
+            name: name.to_string(),
+            running,
+            queued,
+            completed: runner.completed,
+            retried: runner.retried,
+            failed: runner.failed,
+            cancelled: runner.cancelled,
+        };
+
+        match ctx.db.job().name().find(&status.name) {
+            Some(_) => { ctx.db.job().name().update(status); },
+            None => { ctx.db.job().insert(status); }
+        }
+    }
+}
+
+/// Complete finished work and start queued one within budget,
+/// returns completed count
+pub fn proceed<J: Job>(job: &mut J, ctx: &ReducerContext, budget: &mut Budget) -> u32 {
+    job.runner().retry_due(ctx.timestamp);
+
+    let mut completed = 0;
+    while !budget.exhausted() {
+        let Some((pos, output)) = job.runner().next_finished(ctx, J::NAME) else { break };
+        job.complete(ctx, pos, output);
+        ChunkStatus::set(ctx, pos, J::DONE);
+        budget.charge(J::STORE_COST);
+        completed += 1;
+    }
+
+    while !budget.exhausted() {
+        let Some(pos) = job.runner().queue.pop() else { break };
+        let Some(work) = job.start(ctx, pos) else {
+            budget.charge(J::SKIP_COST);
+            continue;
+        };
+        budget.charge(J::TASK_COST);
+
+        if !J::INLINE {
+            job.runner().spawn(pos, work);
+            ChunkStatus::set(ctx, pos, J::RUNNING);
+            continue;
+        }
+
+        if let Some(output) = job.runner().finish(ctx, J::NAME, pos, work()) {
+            job.complete(ctx, pos, output);
+            ChunkStatus::set(ctx, pos, J::DONE);
+            completed += 1;
+        }
+    }
+
+    for pos in std::mem::take(&mut job.runner().given_up) {
+        job.give_up(ctx, pos);
+        ChunkStatus::set(ctx, pos, ChunkState::Failed);
+    }
+
+    // Single threaded pool runs spawned work here, its cost is charged on spawn
+    AsyncComputeTaskPool::get().with_local_executor(|executor| while executor.try_tick() {});
+
+    JobStatus::store(ctx, J::NAME, job.runner());
+    completed
+}
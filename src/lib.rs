@@ -10,29 +10,35 @@ mod chunks;
 mod mesher;
 mod player;
 mod assets;
+mod admin;
+mod queue;
+mod budget;
+mod metrics;
+mod jobs;
+mod timestep;
 
 // Ticks per second
 pub const TIPS: i64 = 20;
 pub const TICK: i64 = 1_000_000 / TIPS;
 
-// Tasks per second
-pub const TAPS: i64 = 5;
-pub const TASK: i64 = 1_000_000 / TAPS;
+// Default tick work budget, microseconds
+pub const BUDGET: u64 = TICK as u64 / 2;
+
+// Shares of the budget left for a phase, so later phases
+// progress even when earlier ones have more queued work
+pub const GENERATOR_SHARE: f64 = 0.5;
+pub const MESHER_SHARE: f64 = 0.75;
 
 #[spacetimedb::reducer(init)]
-pub fn init(ctx: &ReducerContext) {
-    AsyncComputeTaskPool::get_or_init(|| TaskPool::new());
+pub fn init(ctx: &ReducerContext) -> Result<(), String> {
+    AsyncComputeTaskPool::get_or_init(TaskPool::new);
     assets::load(ctx);
+    admin::init(ctx);
 
     // todo: setup main server components
     mesher::Mesher::init();
-    chunks::setup(ctx);
-
-    // Tasks proceed schedule loop
-    ctx.db.tasks().insert(TasksSchedule {
-        scheduled_id: 0,
-        scheduled_at: TimeDuration::from_micros(TASK).into(),
-    });
+    metrics::Latency::init();
+    chunks::setup(ctx)?;
 
     // Main ticks loop
     ctx.db.ticks().insert(Ticks {
@@ -40,30 +46,22 @@ pub fn init(ctx: &ReducerContext) {
         scheduled_at: TimeDuration::from_micros(TICK).into(),
         previous: ctx.timestamp,
         tickrate: 0.0,
+        frame: 0,
         tick: 0,
+        sim_time: 0,
+        accumulator: 0,
+        steps: 0,
+        skipped_steps: 0,
+        lag_spikes: 0,
+        worst_lag: 0,
+        budget: BUDGET,
+        budget_used: 0,
+        cost_scale: 1.0,
         generator_tasks: 0,
         generator_queue: 0,
         mesher_tasks: 0,
         mesher_queue: 0
     });
-}
-
-#[table(name = tasks, scheduled(proceed_tasks))]
-pub struct TasksSchedule {
-    #[auto_inc]
-    #[primary_key]
-    scheduled_id: u64,
-    scheduled_at: ScheduleAt
-}
-
-#[reducer]
-fn proceed_tasks(ctx: &ReducerContext, _arg: TasksSchedule) -> Result<(), String> {
-    if ctx.sender != ctx.identity() {
-        return Err("Task pool not be invoked only via scheduling.".into());
-    }
-
-    AsyncComputeTaskPool::get()
-        .with_local_executor(|executor| { executor.try_tick() });
 
     Ok(())
 }
@@ -76,7 +74,26 @@ pub struct Ticks {
 
     previous: Timestamp,
     pub tickrate: f64,
+    // Scheduled runs of this reducer
+    pub frame: u64,
+
+    // Simulation steps and time, advance by fixed `TICK`
+    // steps regardless of schedule jitter, see `timestep`
     pub tick: u128,
+    pub sim_time: u64,
+    accumulator: u64,
+    pub steps: u32,
+
+    // Steps dropped after stalls and intervals over `SPIKE_STEPS` ticks
+    pub skipped_steps: u64,
+    pub lag_spikes: u32,
+    pub worst_lag: u64,
+
+    // Work budget per tick and its estimated use, microseconds
+    pub budget: u64,
+    pub budget_used: u64,
+    // Correction of work cost estimates, see `Budget::adapt`
+    pub cost_scale: f64,
 
     // Generator and mesher load
     generator_tasks: u32,
@@ -85,6 +102,12 @@ pub struct Ticks {
     mesher_queue: u32,
 }
 
+/// Fixed step game systems entry point, called once per
+/// simulation step with its number and time in microseconds
+fn simulate(_ctx: &ReducerContext, _tick: u128, _time: u64) {
+    // todo: game systems (physics, entities)
+}
+
 #[reducer]
 fn run_tick(ctx: &ReducerContext, mut arg: Ticks) -> Result<(), String> {
     if ctx.sender != ctx.identity() {
@@ -92,16 +115,70 @@ fn run_tick(ctx: &ReducerContext, mut arg: Ticks) -> Result<(), String> {
     }
 
     // Update stats
-    arg.tick += 1;
+    arg.frame += 1;
     let delta = ctx.timestamp.duration_since(arg.previous).unwrap();
     arg.tickrate = 1.0 / delta.as_secs_f64();
     arg.previous = ctx.timestamp;
 
-    // Run generator tasks
-    chunks::proceed_generator(ctx);
+    // Advance simulation by whole steps
+    let interval = delta.as_micros() as u64;
+    let advance = timestep::advance(arg.accumulator, interval, TICK as u64);
+    arg.accumulator = advance.accumulator;
+    arg.steps = advance.steps;
+    for _ in 0..advance.steps {
+        arg.tick += 1;
+        arg.sim_time += TICK as u64;
+        simulate(ctx, arg.tick, arg.sim_time);
+    }
+    arg.skipped_steps += advance.skipped as u64;
 
-    // Run mesher tasks
-    mesher::proceed_mesher(ctx);
+    if timestep::is_spike(interval, TICK as u64) {
+        arg.lag_spikes += 1;
+        arg.worst_lag = arg.worst_lag.max(interval);
+        log::warn!("Tick {} lagged {}ms, skipped {} steps", arg.frame, interval / 1000, advance.skipped);
+    }
+
+    arg.cost_scale = budget::Budget::adapt(arg.cost_scale, delta.as_micros() as i64, TICK);
+    let mut budget = budget::Budget::new(arg.budget, arg.cost_scale);
+
+    // Run generator tasks, with half of the budget at most
+    let mut phase = budget.share(GENERATOR_SHARE);
+    let chunks_generated = chunks::proceed_generator(ctx, &mut phase);
+    let generator_us = phase.spent();
+    budget.merge(phase);
+
+    // Run mesher tasks, leaving a part for eviction
+    let mut phase = budget.share(MESHER_SHARE);
+    let meshes_built = mesher::proceed_mesher(ctx, &mut phase);
+    let mesher_us = phase.spent();
+    budget.merge(phase);
+
+    // Drop work and unload chunks far from players
+    let centers = player::scanner_chunks(ctx);
+    let mut phase = budget.share(1.0);
+    let jobs_cancelled = chunks::proceed_cancellation(ctx, &centers, &mut phase);
+    let chunks_evicted = chunks::proceed_eviction(ctx, &centers, &mut phase);
+    let eviction_us = phase.spent();
+    budget.merge(phase);
+    arg.budget_used = budget.spent();
+
+    let (latency_avg_us, latency_p95_us) = metrics::Latency::stats();
+    metrics::Metrics::record(ctx, metrics::Metrics {
+        tick: arg.frame,
+        timestamp: ctx.timestamp,
+        steps: advance.steps,
+        lag_us: interval.saturating_sub(TICK as u64),
+        generator_us,
+        mesher_us,
+        eviction_us,
+        latency_avg_us,
+        latency_p95_us,
+        chunks_generated,
+        meshes_built,
+        chunks_evicted,
+        jobs_cancelled,
+        loaded_chunks: chunks::LoadArea::len() as u32,
+    });
 
     (arg.generator_tasks, arg.generator_queue) = chunks::Generator::load();
     (arg.mesher_tasks, arg.mesher_queue) = mesher::Mesher::load();
@@ -8,6 +8,25 @@ pub struct StIVec3 {
     pub z: i32,
 }
 
+impl StIVec3 {
+    const KEY_BITS: u32 = 21;
+    const KEY_MASK: u64 = (1 << Self::KEY_BITS) - 1;
+
+    // Coords range with unique keys, wider values alias
+    pub const KEY_MIN: i32 = -(1 << (Self::KEY_BITS - 1));
+    pub const KEY_MAX: i32 = (1 << (Self::KEY_BITS - 1)) - 1;
+
+    /// Pack position into an indexable key,
+    /// 21 bits per axis (enough for chunk coords)
+    pub fn key(&self) -> u64 {
+        let pack = |v: i32| v as u64 & Self::KEY_MASK;
+
+        pack(self.x)
+        | pack(self.y) << Self::KEY_BITS
+        | pack(self.z) << (Self::KEY_BITS * 2)
+    }
+}
+
 impl From<IVec3> for StIVec3 {
     fn from(value: IVec3) -> Self {
         StIVec3 {
@@ -5,26 +5,36 @@ use std::{
 
 use super::{
     math::*,
-    chunks::{SIZE_I32, ChunksRefs, Block, BlocksHandler}
+    budget::Budget,
+    metrics::Latency,
+    jobs::{self, Job, Runner},
+    chunks::{SIZE_I32, ChunksRefs, Block, BlocksHandler, Generator, LoadArea, ChunkStatus, ChunkState}
 };
-use bevy_tasks::{block_on, AsyncComputeTaskPool, Task};
 use spacetimedb::{table, ReducerContext, Table};
 
 #[derive(Debug)]
 pub struct Mesher {
-    pub queue: Vec<IVec3>,
-    pub tasks: HashMap<IVec3, Task<Mesh>>
+    pub jobs: Runner<Mesh>,
+    // Meshes waiting for neighbours generation
+    waiting: HashMap<IVec3, HashSet<IVec3>>,
+    // Chunk position to meshes waiting for it
+    dependents: HashMap<IVec3, HashSet<IVec3>>,
+    // Chunk position to meshes built with air in its place
+    fallbacks: HashMap<IVec3, HashSet<IVec3>>,
+    // Mesh position to its air neighbours, reverse of `fallbacks`
+    air: HashMap<IVec3, HashSet<IVec3>>,
 }
 
 static VALUE: OnceLock<RwLock<Mesher>> = OnceLock::new();
 
 impl Mesher {
-    pub const MAX_TASKS: usize = 16;
-
     pub fn new() -> Self {
         Self {
-            queue: Vec::new(),
-            tasks: HashMap::new()
+            jobs: Runner::new(),
+            waiting: HashMap::new(),
+            dependents: HashMap::new(),
+            fallbacks: HashMap::new(),
+            air: HashMap::new(),
         }
     }
 
@@ -36,10 +46,173 @@ impl Mesher {
         VALUE.get().unwrap()
     }
 
+    fn wait(&mut self, ctx: &ReducerContext, pos: IVec3, missing: HashSet<IVec3>) {
+        // Own chunk is still generating, its state is kept
+        if !missing.contains(&pos) {
+            ChunkStatus::set(ctx, pos, ChunkState::Waiting);
+        }
+
+        for chunk in &missing {
+            self.dependents.entry(*chunk).or_default().insert(pos);
+        }
+
+        self.waiting.insert(pos, missing);
+    }
+
+    fn fallback(&mut self, pos: IVec3, chunk: IVec3) {
+        self.fallbacks.entry(chunk).or_default().insert(pos);
+        self.air.entry(pos).or_default().insert(chunk);
+    }
+
+    // Mesh is gone or rebuilt, its air neighbours no longer matter
+    fn drop_fallbacks(&mut self, pos: &IVec3) {
+        for chunk in self.air.remove(pos).unwrap_or_default() {
+            if let Some(meshes) = self.fallbacks.get_mut(&chunk) {
+                meshes.remove(pos);
+                if meshes.is_empty() {
+                    self.fallbacks.remove(&chunk);
+                }
+            }
+        }
+    }
+
+    fn unwait(&mut self, pos: &IVec3) -> bool {
+        self.drop_fallbacks(pos);
+
+        let Some(missing) = self.waiting.remove(pos) else { return false };
+        for chunk in missing {
+            if let Some(dependents) = self.dependents.get_mut(&chunk) {
+                dependents.remove(pos);
+                if dependents.is_empty() {
+                    self.dependents.remove(&chunk);
+                }
+            }
+        }
+
+        true
+    }
+
+    // Requeue meshes waiting only for this chunk
+    fn wake(&mut self, chunk: IVec3) {
+        for pos in self.dependents.remove(&chunk).unwrap_or_default() {
+            let Some(missing) = self.waiting.get_mut(&pos) else { continue };
+            missing.remove(&chunk);
+
+            if missing.is_empty() {
+                self.waiting.remove(&pos);
+                self.jobs.queue.push(pos);
+            }
+        }
+    }
+
+    /// Queued, running and waiting meshes
+    pub fn pending(&self) -> Vec<IVec3> {
+        let mut pending = self.jobs.pending();
+        pending.extend(self.waiting.keys());
+        pending
+    }
+
+    /// Drop queued, running or waiting mesh
+    pub fn cancel(&mut self, pos: &IVec3) -> bool {
+        self.drop_fallbacks(pos);
+        self.jobs.cancel(pos) | self.unwait(pos)
+    }
+
+    /// Chunk generation was cancelled, waiting meshes treat it as air
+    pub fn abandon(&mut self, chunk: IVec3) {
+        self.wake(chunk);
+    }
+
+    /// Wake meshes waiting for the generated chunk
+    pub fn notify(&mut self, chunk: IVec3) {
+        self.wake(chunk);
+
+        // Meshes built with air in place of this chunk are stale now
+        let stale = self.fallbacks.remove(&chunk).unwrap_or_default();
+        for pos in &stale {
+            if let Some(air) = self.air.get_mut(pos) {
+                air.remove(&chunk);
+                if air.is_empty() {
+                    self.air.remove(pos);
+                }
+            }
+        }
+        self.jobs.queue.extend(stale);
+    }
+
     // Mesher load stats
     pub fn load() -> (u32, u32) {
-        let access = Self::get().read().unwrap();
-        (access.tasks.len() as u32, access.queue.len() as u32)
+        Self::get().read().unwrap().jobs.load()
+    }
+}
+
+impl Job for Mesher {
+    type Output = Mesh;
+
+    const NAME: &'static str = "mesher";
+    const TASK_COST: u64 = 1_000;
+    const STORE_COST: u64 = 200;
+    // Empty and occlusion checks, mesh delete and status write
+    const SKIP_COST: u64 = 150;
+    const RUNNING: ChunkState = ChunkState::Meshing;
+    const DONE: ChunkState = ChunkState::Meshed;
+
+    fn runner(&mut self) -> &mut Runner<Mesh> {
+        &mut self.jobs
+    }
+
+    fn start(&mut self, ctx: &ReducerContext, pos: IVec3) -> Option<impl FnOnce() -> Result<Mesh, String> + Send + 'static> {
+        if self.waiting.contains_key(&pos) {
+            return None;
+        }
+
+        // Chunk will be generated later
+        let generator = Generator::get().read().unwrap();
+        let pending = |p: &IVec3| generator.jobs.is_pending(p);
+
+        let Some(chunk) = LoadArea::get(&pos) else {
+            // Nothing to mesh if chunk is never generated
+            if pending(&pos) {
+                self.wait(ctx, pos, HashSet::from([pos]));
+            }
+
+            return None;
+        };
+
+        if chunk.is_empty() {
+            Mesh::skip(ctx, pos, ChunkState::Empty);
+            return None;
+        }
+
+        // Wait for pending neighbours, others are treated as air
+        let (missing, air): (Vec<IVec3>, Vec<IVec3>) = ChunksRefs::OFFSETS[1..].iter()
+            .map(|offset| pos + *offset)
+            .filter(|n| LoadArea::get(n).is_none())
+            .partition(|n| pending(n));
+
+        if !missing.is_empty() {
+            self.wait(ctx, pos, missing.into_iter().collect());
+            return None;
+        }
+
+        self.drop_fallbacks(&pos);
+        for n in air {
+            self.fallback(pos, n);
+        }
+
+        let refs = ChunksRefs::new(pos)?;
+        if Mesh::is_occluded(&refs) {
+            Mesh::skip(ctx, pos, ChunkState::Occluded);
+            return None;
+        }
+
+        Some(move || build_mesh(pos, refs))
+    }
+
+    fn complete(&mut self, ctx: &ReducerContext, pos: IVec3, mesh: Mesh) {
+        log::info!("Builded mesh: {}", pos);
+        mesh.store(ctx);
+        Latency::ready(pos, ctx.timestamp);
     }
 }
 
@@ -92,7 +265,7 @@ impl Direction {
         }
     }
     
-    pub fn to_u32(&self) -> u32 {
+    pub fn to_u32(self) -> u32 {
         match self {
             Self::Up => 0,
             Self::Left => 1,
@@ -176,16 +349,15 @@ impl Vertex {
     /// [1]bit - UVx (0/1)
     /// [1]bit - UVy (0/1)
     /// [12]bits - block id (also texture id) (0-4095)
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(local: IVec3, dir: Direction, block: &Block, uv: &UVec2) -> u32 {
-        let data = local.x as u32
+        local.x as u32
         | (local.y as u32) << 5u32
         | (local.z as u32) << 10u32
         | (dir.to_u32()) << 15u32
         | (uv.x) << 18u32
         | (uv.y) << 19u32
-        | (block.id as u32) << 20u32;
-        
-        data
+        | (block.id as u32) << 20u32
     }
 }
 
@@ -197,16 +369,96 @@ pub struct Mesh {
     #[primary_key]
     id: u64,
 
+    // Packed position, see `StIVec3::key`
     #[unique]
+    key: u64,
     position: StIVec3,
     vertices: Vec<u32>,
     indices: Vec<u32>,
 }
 
 impl Mesh {
-    fn make_vertices(dir: Direction, refs: &ChunksRefs) -> Vec<u32> {
+    pub fn exists(ctx: &ReducerContext, pos: IVec3) -> bool {
+        ctx.db.mesh().key().find(StIVec3::from(pos).key()).is_some()
+    }
+
+    pub fn remove(ctx: &ReducerContext, pos: IVec3) {
+        ctx.db.mesh().key().delete(StIVec3::from(pos).key());
+    }
+
+    /// Drop meshes of chunks that are not loaded
+    pub fn remove_unloaded(ctx: &ReducerContext) {
+        for mesh in ctx.db.mesh().iter() {
+            if LoadArea::get(&mesh.position.into()).is_none() {
+                ctx.db.mesh().id().delete(mesh.id);
+            }
+        }
+    }
+
+    // Chunk has no visible faces, drop its old mesh
+    fn skip(ctx: &ReducerContext, pos: IVec3, state: ChunkState) {
+        Self::remove(ctx, pos);
+        ChunkStatus::set(ctx, pos, state);
+        Latency::ready(pos, ctx.timestamp);
+    }
+
+    /// Chunk and face layers of its neighbours are all solid
+    pub fn is_occluded(refs: &ChunksRefs) -> bool {
+        let Ok(handler) = BlocksHandler::get().read() else { return false };
+        let solid = |pos: IVec3| refs.get_block(pos)
+            .and_then(|id| handler.block(id).ok())
+            .is_some_and(|b| b.is_meshable());
+        let inside = |v: i32| (0..SIZE_I32).contains(&v);
+
+        for x in -1..=SIZE_I32 {
+            for y in -1..=SIZE_I32 {
+                for z in -1..=SIZE_I32 {
+                    // Edges and corners touch no faces
+                    let outside = [x, y, z].into_iter().filter(|v| !inside(*v)).count();
+                    if outside <= 1 && !solid(ivec3(x, y, z)) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    // Insert mesh or replace the existing one at the same position
+    pub fn store(mut self, ctx: &ReducerContext) {
+        match ctx.db.mesh().key().find(self.key) {
+            Some(old) => {
+                self.id = old.id;
+                ctx.db.mesh().id().update(self);
+            },
+            None => { ctx.db.mesh().insert(self); }
+        }
+    }
+
+    // Get block from registry, unknown ids are replaced with placeholder
+    fn lookup(
+        handler: &BlocksHandler,
+        refs: &ChunksRefs,
+        pos: IVec3,
+        reported: &mut HashSet<u16>
+    ) -> Result<Arc<Block>, String> {
+        let id = refs.get_block(pos)
+            .ok_or_else(|| format!("Block {} is outside of chunk {} neighbours", pos, refs.position()))?;
+
+        Ok(handler.block(id).unwrap_or_else(|e| {
+            if reported.insert(id) {
+                log::warn!("{} in chunk {} at {}", e, refs.position(), pos);
+            }
+
+            handler.unknown()
+        }))
+    }
+
+    fn make_vertices(dir: Direction, refs: &ChunksRefs) -> Result<Vec<u32>, String> {
         let mut vertices = Vec::new();
-        let handler = BlocksHandler::get().read().unwrap();
+        let mut reported = HashSet::new();
+        let handler = BlocksHandler::get().read().map_err(|e| e.to_string())?;
 
         // Culled meshser
         for axis in 0..SIZE_I32 {
@@ -215,8 +467,8 @@ impl Mesh {
                 let column = i / SIZE_I32;
                 let pos = dir.world_sample(axis, row, column);
 
-                let current = handler.block(refs.get_block(pos)).unwrap();
-                let neg_z = handler.block(refs.get_block(pos + dir.air_sample())).unwrap();
+                let current = Self::lookup(&handler, refs, pos, &mut reported)?;
+                let neg_z = Self::lookup(&handler, refs, pos + dir.air_sample(), &mut reported)?;
 
                 if current.is_meshable() && !neg_z.is_meshable() {
                     let face = Face::new(row, column);
@@ -225,25 +477,25 @@ impl Mesh {
             }
         }
 
-        vertices
+        Ok(vertices)
     }
 
-    pub fn build(refs: ChunksRefs) -> Vec<u32> {
+    pub fn build(refs: ChunksRefs) -> Result<Vec<u32>, String> {
         let mut vertices = Vec::new();
 
         // Apply all directions
         for dir in Direction::iter() {
-            vertices.extend(Self::make_vertices(dir, &refs));
+            vertices.extend(Self::make_vertices(dir, &refs)?);
         }
         
-        vertices
+        Ok(vertices)
     }
 
-    pub fn generate_indices(vertices: &Vec<u32>) -> Vec<u32> {
+    pub fn generate_indices(vertices: &[u32]) -> Vec<u32> {
         let indices_count = vertices.len() / 4;
         let mut indices = Vec::<u32>::with_capacity(indices_count);
         
-        (0..indices_count).into_iter().for_each(|vert_index| {
+        (0..indices_count).for_each(|vert_index| {
             let vert_index = vert_index as u32 * 4u32;
             indices.push(vert_index);
             indices.push(vert_index + 1);
@@ -257,42 +509,21 @@ impl Mesh {
     }
 }
 
-pub async fn build_mesh(pos: IVec3, refs: ChunksRefs) -> Mesh {
-    let vertices = Mesh::build(refs);
+pub fn build_mesh(pos: IVec3, refs: ChunksRefs) -> Result<Mesh, String> {
+    let vertices = Mesh::build(refs)?;
     let indices = Mesh::generate_indices(&vertices);
 
-    Mesh {
+    let position: StIVec3 = pos.into();
+    Ok(Mesh {
         id: 0,
-        position: pos.into(),
+        key: position.key(),
+        position,
         vertices,
         indices
-    }
+    })
 }
 
-pub fn proceed_mesher(ctx: &ReducerContext) {
-    let task_pool = AsyncComputeTaskPool::get();
-    let mut mesher = Mesher::get().write().unwrap();
-
-    for (pos, task) in mesher.tasks.drain().collect::<Vec<_>>() {
-        if !task.is_finished() {
-            mesher.tasks.insert(pos, task);
-            continue;
-        }
-
-        let mesh = block_on(task);
-        log::info!("Builded mesh: {}", pos);
-        ctx.db.mesh().insert(mesh);
-    }
-
-    let l = mesher.queue.len().min(Mesher::MAX_TASKS - mesher.tasks.len());
-    for pos in mesher.queue.drain(0..l).collect::<Vec<IVec3>>() {
-        let Some(refs) = ChunksRefs::new(pos) else {
-            mesher.queue.push(pos);
-            continue;
-        };
-
-        let task = task_pool.spawn(build_mesh(pos, refs));
-        mesher.tasks.insert(pos, task);
-    }
-
+/// Store finished meshes and spawn new tasks, returns built meshes count
+pub fn proceed_mesher(ctx: &ReducerContext, budget: &mut Budget) -> u32 {
+    jobs::proceed(&mut *Mesher::get().write().unwrap(), ctx, budget)
 }
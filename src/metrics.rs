@@ -0,0 +1,109 @@
+use std::{
+    collections::*,
+    sync::*,
+};
+use spacetimedb::{table, ReducerContext, Table, Timestamp};
+
+use super::math::*;
+
+// Ticks kept in metrics table
+pub const WINDOW: u64 = 200;
+// Latency samples used for average and p95
+pub const SAMPLES: usize = 256;
+
+/// Rolling per-tick server metrics, last `WINDOW` ticks
+#[table(name = metrics, public)]
+pub struct Metrics {
+    // Scheduled tick run, `Ticks::frame`
+    #[primary_key]
+    pub tick: u64,
+    pub timestamp: Timestamp,
+
+    // Simulation steps run and lateness of this tick, microseconds
+    pub steps: u32,
+    pub lag_us: u64,
+
+    // Estimated phase durations, microseconds,
+    // eviction includes cancelling out of view jobs
+    pub generator_us: u64,
+    pub mesher_us: u64,
+    pub eviction_us: u64,
+
+    // Time from enqueue to chunk ready, microseconds
+    pub latency_avg_us: u64,
+    pub latency_p95_us: u64,
+
+    // Work done this tick
+    pub chunks_generated: u32,
+    pub meshes_built: u32,
+    pub chunks_evicted: u32,
+    // Queued or running jobs dropped out of view
+    pub jobs_cancelled: u32,
+
+    pub loaded_chunks: u32,
+}
+
+impl Metrics {
+    // Insert tick row and drop the one leaving the window
+    pub fn record(ctx: &ReducerContext, metrics: Metrics) {
+        if let Some(old) = metrics.tick.checked_sub(WINDOW) {
+            ctx.db.metrics().tick().delete(old);
+        }
+
+        ctx.db.metrics().insert(metrics);
+    }
+}
+
+#[derive(Debug, Default)]
+/// Enqueue timestamps and recent enqueue to ready samples
+pub struct Latency {
+    enqueued: HashMap<IVec3, Timestamp>,
+    samples: VecDeque<u64>,
+}
+
+static VALUE: OnceLock<RwLock<Latency>> = OnceLock::new();
+
+impl Latency {
+    pub fn init() {
+        VALUE.set(RwLock::new(Self::default())).unwrap();
+    }
+
+    pub fn get() -> &'static RwLock<Self> {
+        VALUE.get().unwrap()
+    }
+
+    // Keeps the first enqueue time if chunk is queued again
+    pub fn enqueued(pos: IVec3, at: Timestamp) {
+        Self::get().write().unwrap().enqueued.entry(pos).or_insert(at);
+    }
+
+    pub fn cancel(pos: IVec3) {
+        Self::get().write().unwrap().enqueued.remove(&pos);
+    }
+
+    pub fn ready(pos: IVec3, at: Timestamp) {
+        let mut access = Self::get().write().unwrap();
+        let Some(since) = access.enqueued.remove(&pos) else { return };
+
+        let latency = at.duration_since(since).map_or(0, |d| d.as_micros() as u64);
+        if access.samples.len() == SAMPLES {
+            access.samples.pop_front();
+        }
+        access.samples.push_back(latency);
+    }
+
+    /// Average and p95 of recent samples
+    pub fn stats() -> (u64, u64) {
+        let access = Self::get().read().unwrap();
+        if access.samples.is_empty() {
+            return (0, 0);
+        }
+
+        let mut sorted = access.samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+
+        let avg = sorted.iter().sum::<u64>() / sorted.len() as u64;
+        let p95 = sorted[(sorted.len() * 95 / 100).min(sorted.len() - 1)];
+        (avg, p95)
+    }
+}
@@ -1,4 +1,4 @@
-use super::math::*;
+use super::{math::*, chunks, mesher};
 use spacetimedb::{
     reducer, table, Table,
     Identity, ReducerContext,
@@ -54,19 +54,96 @@ pub fn create_player(ctx: &ReducerContext, name: String) -> Result<(), String> {
     Ok(())
 }
 
+// Chunks players are in
+pub fn scanner_chunks(ctx: &ReducerContext) -> Vec<IVec3> {
+    ctx.db.scanner().iter().map(|s| s.chunk.into()).collect()
+}
+
+// Chunk containing world position
+fn chunk_at(position: Vec3) -> IVec3 {
+    chunks::split_world(position.floor().as_ivec3()).0
+}
+
+/// Order generation and meshing by distance to players scanners
+pub fn reprioritize(ctx: &ReducerContext) {
+    let centers = scanner_chunks(ctx);
+
+    chunks::Generator::get().write().unwrap().jobs.queue.set_centers(centers.clone());
+    mesher::Mesher::get().write().unwrap().jobs.queue.set_centers(centers);
+}
+
+// Move player scanner and load chunks around it
+fn update_scanner(ctx: &ReducerContext, identity: Identity, chunk: IVec3) {
+    ctx.db.scanner().identity().delete(identity);
+    ctx.db.scanner().insert(Scanner { identity, chunk: chunk.into() });
+
+    let radius = chunks::Context::get().read().unwrap().config.loading.view_radius;
+    chunks::load_around(ctx, chunk, radius);
+    reprioritize(ctx);
+}
+
 #[reducer]
 pub fn join(ctx: &ReducerContext) -> Result<(), String> {
     let Some(mut player) = ctx.db.player().identity().find(ctx.sender) else {
         return Err("Player is not exists!".to_string());
     };
 
-    if !player.online {
+    if player.online {
         return Err("Player is already joined".to_string())
     }
 
     player.online = true;
+    let chunk = chunk_at(player.position.into());
     ctx.db.player().identity().update(player);
 
+    // Load chunks around player
+    update_scanner(ctx, ctx.sender, chunk);
+
     Ok(())
 }
 
+
+// Force player offline and stop loading chunks for them
+pub fn kick(ctx: &ReducerContext, identity: Identity) -> Result<(), String> {
+    let Some(mut player) = ctx.db.player().identity().find(identity) else {
+        return Err("Player is not exists!".to_string());
+    };
+
+    player.online = false;
+    ctx.db.player().identity().update(player);
+    ctx.db.scanner().identity().delete(identity);
+    reprioritize(ctx);
+
+    Ok(())
+}
+
+// Players go offline on disconnect, so they can join again
+#[reducer(client_disconnected)]
+pub fn leave(ctx: &ReducerContext) {
+    if ctx.db.player().identity().find(ctx.sender).is_some() {
+        let _ = kick(ctx, ctx.sender);
+    }
+}
+
+#[reducer]
+pub fn move_player(ctx: &ReducerContext, position: StVec3) -> Result<(), String> {
+    let Some(mut player) = ctx.db.player().identity().find(ctx.sender) else {
+        return Err("Player is not exists!".to_string());
+    };
+
+    if !player.online {
+        return Err("Player is not joined".to_string());
+    }
+
+    let previous = chunk_at(player.position.into());
+    player.position = position;
+    ctx.db.player().identity().update(player);
+
+    // Scanner follows player between chunks
+    let chunk = chunk_at(position.into());
+    if chunk != previous {
+        update_scanner(ctx, ctx.sender, chunk);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::{
+    cmp::Reverse,
+    collections::*,
+};
+
+use super::math::*;
+
+// Priority and position, smallest first
+type Entry = Reverse<(u64, (i32, i32, i32))>;
+
+/// Chunk positions queue ordered by distance to the nearest center
+/// (players scanners), each position is queued at most once
+#[derive(Debug, Default)]
+pub struct ChunkQueue {
+    items: HashSet<IVec3>,
+    // Lazy heap, entries missing from `items` are skipped
+    heap: BinaryHeap<Entry>,
+    centers: Vec<IVec3>,
+}
+
+impl ChunkQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Squared distance to the nearest center, origin if there are none
+    fn priority(&self, pos: IVec3) -> u64 {
+        let distance = |c: &IVec3| (pos - *c).as_i64vec3().length_squared() as u64;
+
+        match self.centers.is_empty() {
+            true => distance(&IVec3::ZERO),
+            false => self.centers.iter().map(distance).min().unwrap()
+        }
+    }
+
+    /// Push position, returns false if it is already queued
+    pub fn push(&mut self, pos: IVec3) -> bool {
+        if !self.items.insert(pos) {
+            return false;
+        }
+
+        self.heap.push(Reverse((self.priority(pos), pos.into())));
+        true
+    }
+
+    pub fn extend(&mut self, positions: impl IntoIterator<Item = IVec3>) {
+        for pos in positions {
+            self.push(pos);
+        }
+    }
+
+    /// Pop the nearest position
+    pub fn pop(&mut self) -> Option<IVec3> {
+        while let Some(Reverse((_, pos))) = self.heap.pop() {
+            let pos = IVec3::from(pos);
+            if self.items.remove(&pos) {
+                return Some(pos);
+            }
+        }
+
+        None
+    }
+
+    /// Remove position, its heap entry is skipped later
+    pub fn remove(&mut self, pos: &IVec3) -> bool {
+        self.items.remove(pos)
+    }
+
+    pub fn contains(&self, pos: &IVec3) -> bool {
+        self.items.contains(pos)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &IVec3> {
+        self.items.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Replace centers and reorder queued positions
+    pub fn set_centers(&mut self, centers: Vec<IVec3>) {
+        if self.centers == centers {
+            return;
+        }
+
+        self.centers = centers;
+        self.heap = self.items.iter()
+            .map(|pos| Reverse((self.priority(*pos), (*pos).into())))
+            .collect();
+    }
+}
@@ -0,0 +1,79 @@
+/// Fixed timestep accumulator.
+///
+/// Scheduled ticks drift and stall under load, so simulation
+/// advances by whole `TICK` steps from real elapsed time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Advance {
+    // Steps to simulate now
+    pub steps: u32,
+    // Steps dropped to catch up after a long stall
+    pub skipped: u32,
+    // Time left for the next tick, microseconds
+    pub accumulator: u64,
+}
+
+// Most steps simulated in one tick, the rest is dropped
+pub const MAX_STEPS: u32 = 4;
+// Interval treated as lag spike, in steps
+pub const SPIKE_STEPS: u64 = 3;
+
+pub fn advance(accumulator: u64, delta: u64, step: u64) -> Advance {
+    let total = accumulator + delta;
+    let due = total / step;
+
+    match due > MAX_STEPS as u64 {
+        // Too far behind, keep only the fraction of a step
+        true => Advance {
+            steps: MAX_STEPS,
+            skipped: (due - MAX_STEPS as u64).min(u32::MAX as u64) as u32,
+            accumulator: total % step,
+        },
+        false => Advance {
+            steps: due as u32,
+            skipped: 0,
+            accumulator: total - due * step,
+        }
+    }
+}
+
+pub fn is_spike(delta: u64, step: u64) -> bool {
+    delta > step * SPIKE_STEPS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: u64 = 50_000;
+
+    #[test]
+    fn keeps_remainder_for_next_tick() {
+        let advance = advance(0, 70_000, STEP);
+        assert_eq!(advance, Advance { steps: 1, skipped: 0, accumulator: 20_000 });
+
+        let advance = advance_from(advance, 40_000);
+        assert_eq!(advance, Advance { steps: 1, skipped: 0, accumulator: 10_000 });
+    }
+
+    #[test]
+    fn early_tick_runs_no_steps() {
+        assert_eq!(advance(0, 30_000, STEP), Advance { steps: 0, skipped: 0, accumulator: 30_000 });
+    }
+
+    #[test]
+    fn catches_up_after_delay() {
+        assert_eq!(advance(10_000, 140_000, STEP), Advance { steps: 3, skipped: 0, accumulator: 0 });
+    }
+
+    #[test]
+    fn skips_steps_after_stall() {
+        let advance = advance(0, STEP * 10 + 5_000, STEP);
+        assert_eq!(advance, Advance { steps: MAX_STEPS, skipped: 10 - MAX_STEPS, accumulator: 5_000 });
+        assert!(is_spike(STEP * 10, STEP));
+        assert!(!is_spike(STEP, STEP));
+    }
+
+    fn advance_from(previous: Advance, delta: u64) -> Advance {
+        advance(previous.accumulator, delta, STEP)
+    }
+}